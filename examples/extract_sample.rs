@@ -5,7 +5,7 @@ use tokio::{
     fs::{self, File},
     io::{BufReader, BufWriter, copy},
 };
-use xp3::read::XP3Archive;
+use xp3::{name::sanitize_path, read::XP3Archive};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -15,9 +15,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let dir = Path::new("xp3_test");
     for i in 0..xp3.entries().len() {
         let entry = &xp3.entries()[i];
-        println!("Extracting: {}", entry.name);
+        let sanitized = sanitize_path(&entry.name);
+        if sanitized.path.file_name().is_none() {
+            println!("Skipping: {:?}", entry.name);
+            continue;
+        }
+        println!("Extracting: {} {:?}", entry.name, sanitized.fixes);
 
-        let path = dir.join(&entry.name);
+        let path = dir.join(sanitized.path);
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).await?;
         }
@@ -0,0 +1,14 @@
+use core::error::Error;
+use std::{fs::File, io::BufReader};
+
+use xp3::sync::XP3Archive;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let stream = BufReader::new(File::open("sample.xp3")?);
+    let xp3 = XP3Archive::open(stream)?;
+
+    for entry in xp3.entries() {
+        println!("file: {:?}", entry);
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use xp3::{limits::XP3Limits, options::XP3ArchiveOptions};
+
+/// Limits keeping memory of a single run small
+const LIMITS: XP3Limits = XP3Limits {
+    max_index_size: 1 << 20,
+    ..XP3Limits::DEFAULT
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut archive) = XP3ArchiveOptions::new().limits(LIMITS).open(Cursor::new(data)) {
+        let _ = archive.validate();
+    }
+});
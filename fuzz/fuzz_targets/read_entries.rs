@@ -0,0 +1,40 @@
+#![no_main]
+
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+use libfuzzer_sys::fuzz_target;
+use xp3::{limits::XP3Limits, options::XP3ArchiveOptions};
+
+/// Limits keeping memory of a single run small
+const LIMITS: XP3Limits = XP3Limits {
+    max_index_size: 1 << 20,
+    ..XP3Limits::DEFAULT
+};
+
+fuzz_target!(|data: &[u8]| {
+    let mut options = XP3ArchiveOptions::new();
+    options.limits(LIMITS);
+    if let Ok(mut archive) = options.open(Cursor::new(data)) {
+        archive.set_verify_checksum(true);
+        for index in 0..archive.entries().len() {
+            let Ok(mut file) = archive.by_index(index).unwrap() else {
+                continue;
+            };
+
+            let _ = io::copy(&mut file, &mut io::sink());
+            // Seek backward and into the middle of segments
+            for pos in [SeekFrom::Start(1), SeekFrom::End(-3), SeekFrom::Current(-7)] {
+                if file.seek(pos).is_ok() {
+                    let _ = file.read(&mut [0; 16]);
+                }
+            }
+        }
+        let _ = archive.verify_all();
+    }
+
+    if let Ok(archive) = options.open_shared(data) {
+        for index in 0..archive.entries().len() {
+            let _ = archive.bytes(index);
+        }
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use std::io::{self, Cursor};
+
+use libfuzzer_sys::fuzz_target;
+use xp3::sync::XP3Archive;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((mut archive, _)) = XP3Archive::open_recover(Cursor::new(data)) {
+        for index in 0..archive.entries().len() {
+            if let Ok(mut file) = archive.by_index(index).unwrap() {
+                let _ = io::copy(&mut file, &mut io::sink());
+            }
+        }
+    }
+});
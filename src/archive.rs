@@ -0,0 +1,120 @@
+//! Index and reader settings shared by archives of every api
+
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{
+    entry::{IndexNote, XP3Entries},
+    filter::XP3Filter,
+    name::{NameIndex, NameMatching},
+    options::XP3ArchiveOptions,
+};
+
+/// Index of an opened archive with settings of files read from it
+#[derive(Debug)]
+pub(crate) struct ArchiveState {
+    pub entries: XP3Entries,
+    pub names: NameIndex,
+    pub filter: Option<Arc<dyn XP3Filter>>,
+    pub verify_checksum: bool,
+    /// Offset of the archive in its stream
+    pub start: u64,
+    /// Files without `adlr` chunk
+    unchecked: HashSet<usize>,
+}
+
+impl ArchiveState {
+    pub fn new(entries: XP3Entries, start: u64, options: &XP3ArchiveOptions) -> Self {
+        let unchecked = entries
+            .notes
+            .iter()
+            .filter_map(|&note| match note {
+                IndexNote::MissingChecksum(index) => Some(index),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            names: NameIndex::new(options.name_matching, &entries.entries),
+            entries,
+            filter: options.filter.clone(),
+            verify_checksum: options.verify_checksum,
+            start,
+            unchecked,
+        }
+    }
+
+    pub fn set_name_matching(&mut self, matching: NameMatching) {
+        if self.names.matching() != matching {
+            self.names = NameIndex::new(matching, &self.entries.entries);
+        }
+    }
+
+    /// Whether file at `index` is verified if `verify` is requested
+    pub fn verifies(&self, index: usize, verify: bool) -> bool {
+        // Files without adlr chunk have no checksum to verify, zero is a valid adler32 checksum otherwise
+        verify && !self.unchecked.contains(&index)
+    }
+}
+
+/// Public accessors of archive types delegating to their `state` field
+macro_rules! state_accessors {
+    () => {
+        #[inline]
+        /// List entries
+        pub fn entries(&self) -> &[$crate::XP3FileEntry] {
+            &self.state.entries.entries
+        }
+
+        #[inline]
+        /// Unknown top level index chunks
+        pub fn unknown_chunks(&self) -> &[$crate::XP3Chunk] {
+            &self.state.entries.chunks
+        }
+
+        #[inline]
+        /// Name matching used for lookup
+        pub const fn name_matching(&self) -> $crate::name::NameMatching {
+            self.state.names.matching()
+        }
+
+        /// Set name matching used for lookup
+        pub fn set_name_matching(&mut self, matching: $crate::name::NameMatching) {
+            self.state.set_name_matching(matching);
+        }
+
+        #[inline]
+        /// Find index of an entry by name
+        pub fn index_of(&self, name: &str) -> Option<usize> {
+            self.state.names.get(name)
+        }
+
+        #[inline]
+        /// Filter applied to file data
+        pub fn filter(&self) -> Option<&::std::sync::Arc<dyn $crate::filter::XP3Filter>> {
+            self.state.filter.as_ref()
+        }
+
+        /// Set filter applied to file data of files opened afterward
+        pub fn set_filter(
+            &mut self,
+            filter: Option<::std::sync::Arc<dyn $crate::filter::XP3Filter>>,
+        ) {
+            self.state.filter = filter;
+        }
+
+        #[inline]
+        /// Whether checksum of files are verified on read
+        pub const fn verify_checksum(&self) -> bool {
+            self.state.verify_checksum
+        }
+
+        /// Set whether files opened afterward verify checksum of data read.
+        /// Reading fails with [`XP3ReadErrorKind::ChecksumMismatch`](crate::error::XP3ReadErrorKind::ChecksumMismatch)
+        /// at the end of file if data is corrupted.
+        pub fn set_verify_checksum(&mut self, verify: bool) {
+            self.state.verify_checksum = verify;
+        }
+    };
+}
+
+pub(crate) use state_accessors;
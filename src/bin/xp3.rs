@@ -0,0 +1,689 @@
+use core::error::Error;
+use std::{
+    env,
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Seek},
+    path::{Component, Path, PathBuf},
+    process::{self, ExitCode},
+    sync::Arc,
+};
+
+use xp3::{
+    XP3FileEntry, crypt,
+    filter::XP3Filter,
+    header::XP3Version,
+    name::sanitize_path,
+    options::XP3ArchiveOptions,
+    sync::{
+        XP3Archive, XP3Editor, XP3Writer,
+        fs::{ExtractOptions, PackOptions, extract_all, pack_dir, to_timestamp},
+    },
+};
+
+const USAGE: &str = "\
+Usage: xp3 <command> [options]
+
+Commands:
+  list <archive> [--json]             List entries
+  info <archive> [--json]             Show archive summary
+  verify <archive>                    Validate index and verify checksum of every entry
+  extract <archive> <dir>             Extract entries into directory
+  pack <dir> <archive>                Pack directory into new archive
+  add <archive> <file>...             Add files, replacing entries with same name
+  remove <archive> <name>...          Remove entries by name
+  rename <archive> <name> <new name>  Rename entries
+
+Options:
+  --json                    Print machine-readable JSON
+  --compression <0-9>       Compression level of file data, stored if omitted
+  --index-compression <0-9> Compression level of index, stored if omitted
+  --version <old|current[:minor]>
+                            Archive version to write (default: current:1)
+  --protected               Set protected flag of written entries
+  --timestamp               Keep modification time of files
+  --include <glob>          Only pack or extract matching names
+  --exclude <glob>          Skip matching names
+  --rewrite-names           Extract unsafe names under rewritten paths
+  --recover                 Salvage entries of damaged archive when reading
+  --name <name>             Entry name of a single added file
+  --force                   Accept unsafe names given by --name or rename
+  --crypt <scheme>          Encryption scheme (xor, checksum-xor, key-xor, table-xor)
+  --key <hex>               Key of encryption scheme";
+
+/// Options taking a value
+const VALUE_OPTIONS: &[&str] = &[
+    "compression",
+    "index-compression",
+    "version",
+    "include",
+    "exclude",
+    "name",
+    "crypt",
+    "key",
+];
+
+/// Options without value
+const FLAG_OPTIONS: &[&str] = &[
+    "json",
+    "protected",
+    "timestamp",
+    "rewrite-names",
+    "recover",
+    "force",
+];
+
+/// Commands with names of their positional arguments, the last one is repeatable if it ends with `...`
+const COMMANDS: &[(&str, &[&str])] = &[
+    ("list", &["archive"]),
+    ("info", &["archive"]),
+    ("verify", &["archive"]),
+    ("extract", &["archive", "dir"]),
+    ("pack", &["dir", "archive"]),
+    ("add", &["archive", "file..."]),
+    ("remove", &["archive", "name..."]),
+    ("rename", &["archive", "name", "new name"]),
+    ("help", &[]),
+];
+
+#[derive(Debug, Default)]
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    /// Parse arguments, failing on unknown options or commands and wrong number of arguments
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        while let Some(arg) = args.next() {
+            let Some(option) = arg.strip_prefix("--") else {
+                parsed.positional.push(arg);
+                continue;
+            };
+
+            let (option, value) = match option.split_once('=') {
+                Some((option, value)) => (option, Some(value.to_string())),
+                None => (option, None),
+            };
+            let value = match value {
+                _ if !VALUE_OPTIONS.contains(&option) && !FLAG_OPTIONS.contains(&option) => {
+                    return Err(format!("unknown option: --{option}"));
+                }
+                Some(_) if FLAG_OPTIONS.contains(&option) => {
+                    return Err(format!("--{option} takes no value"));
+                }
+                Some(value) => Some(value),
+                None if VALUE_OPTIONS.contains(&option) => Some(
+                    args.next()
+                        .ok_or_else(|| format!("missing value of --{option}"))?,
+                ),
+                None => None,
+            };
+            parsed.options.push((option.to_string(), value));
+        }
+
+        parsed.check_positional()?;
+        Ok(parsed)
+    }
+
+    fn check_positional(&self) -> Result<(), String> {
+        let Some((command, args)) = self.positional.split_first() else {
+            return Err("missing command".to_string());
+        };
+        let Some((_, names)) = COMMANDS.iter().find(|(name, _)| name == command) else {
+            return Err(format!("unknown command: {command}"));
+        };
+
+        if let Some(name) = names.get(args.len()) {
+            return Err(format!("missing {}", name.trim_end_matches("...")));
+        }
+        if !names.last().is_some_and(|name| name.ends_with("..."))
+            && let Some(arg) = args.get(names.len())
+        {
+            return Err(format!("unexpected argument: {arg}"));
+        }
+
+        Ok(())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(option, _)| option == name)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values(name).last()
+    }
+
+    fn values<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> {
+        self.options
+            .iter()
+            .filter(move |(option, _)| option == name)
+            .filter_map(|(_, value)| value.as_deref())
+    }
+
+    fn level(&self, name: &str) -> Result<Option<u8>, String> {
+        self.value(name)
+            .map(|value| match value.parse::<u8>() {
+                Ok(level @ 0..=9) => Ok(level),
+                _ => Err(format!("invalid --{name}: {value}")),
+            })
+            .transpose()
+    }
+
+    fn version(&self) -> Result<Option<XP3Version>, String> {
+        let Some(value) = self.value("version") else {
+            return Ok(None);
+        };
+
+        match value.split_once(':') {
+            None if value == "old" => Ok(Some(XP3Version::Old)),
+            None if value == "current" => Ok(Some(XP3Version::Current { minor: 1 })),
+            Some(("current", minor)) => minor
+                .parse()
+                .map(|minor| Some(XP3Version::Current { minor }))
+                .map_err(|_| format!("invalid --version: {value}")),
+            _ => Err(format!("invalid --version: {value}")),
+        }
+    }
+
+    fn filter(&self) -> Result<Option<Arc<dyn XP3Filter>>, String> {
+        let Some(scheme) = self.value("crypt") else {
+            return Ok(None);
+        };
+
+        let key = self.value("key").unwrap_or_default();
+        let key = (0..key.len())
+            .step_by(2)
+            .map(|i| {
+                key.get(i..i + 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("invalid --key: {key}"))?;
+
+        crypt::by_name(scheme, &key)
+            .map(Some)
+            .ok_or_else(|| format!("invalid --crypt scheme or key: {scheme}"))
+    }
+
+    /// Check entry `name` given by user is safe to extract, unless forced
+    fn new_name(&self, name: &str) -> Result<String, String> {
+        let sanitized = sanitize_path(name);
+        if !self.flag("force")
+            && (!sanitized.fixes.is_empty() || sanitized.path.as_os_str().is_empty())
+        {
+            return Err(format!("unsafe entry name, use --force to keep it: {name}"));
+        }
+        Ok(name.to_string())
+    }
+
+    fn positional(&self, index: usize, name: &str) -> Result<&str, String> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| format!("missing {name}"))
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<ExitCode, Box<dyn Error>> {
+    let command = args.positional(0, "command")?;
+    match command {
+        "list" => list(args)?,
+        "info" => info(args)?,
+        "verify" => return verify(args),
+        "extract" => extract(args)?,
+        "pack" => pack(args)?,
+        "add" => add(args)?,
+        "remove" => remove(args)?,
+        "rename" => rename(args)?,
+        "help" => println!("{USAGE}"),
+        _ => return Err(format!("unknown command: {command}").into()),
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+type Archive = XP3Archive<BufReader<File>>;
+
+fn open(args: &Args) -> Result<Archive, Box<dyn Error>> {
+    let stream = BufReader::new(File::open(args.positional(1, "archive")?)?);
+    let mut options = XP3ArchiveOptions::new();
+    options.filter(args.filter()?);
+    if !args.flag("recover") {
+        return Ok(options.open(stream)?);
+    }
+
+    let (archive, recovery) = options.open_recover(stream)?;
+    if let Some(err) = recovery.error {
+        eprintln!(
+            "damaged archive: {err}, {} entries salvaged and {} carved",
+            recovery.salvaged, recovery.carved
+        );
+    }
+    Ok(archive)
+}
+
+fn list(args: &Args) -> Result<(), Box<dyn Error>> {
+    let archive = open(args)?;
+    if args.flag("json") {
+        let entries = archive.entries().iter().map(entry_json).collect::<Vec<_>>();
+        println!("[{}]", entries.join(","));
+        return Ok(());
+    }
+
+    for entry in archive.entries() {
+        println!(
+            "{:>12} {:>12} {:08x} {} {}",
+            entry.size,
+            entry.archive_size,
+            entry.checksum,
+            if entry.protected { 'P' } else { '-' },
+            entry.name
+        );
+    }
+    Ok(())
+}
+
+fn info(args: &Args) -> Result<(), Box<dyn Error>> {
+    let archive = open(args)?;
+    let version = match archive.version {
+        XP3Version::Old => "old".to_string(),
+        XP3Version::Current { minor } => format!("current:{minor}"),
+    };
+    let size = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.size)
+        .sum::<u64>();
+    let archive_size = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.archive_size)
+        .sum::<u64>();
+    let chunks = archive.unknown_chunks().len();
+
+    if args.flag("json") {
+        println!(
+            "{{\"version\":{},\"files\":{},\"size\":{size},\"archive_size\":{archive_size},\"unknown_chunks\":{chunks}}}",
+            json_string(&version),
+            archive.entries().len(),
+        );
+    } else {
+        println!("version: {version}");
+        println!("files: {}", archive.entries().len());
+        println!("size: {size}");
+        println!("archive size: {archive_size}");
+        println!("unknown chunks: {chunks}");
+    }
+    Ok(())
+}
+
+fn verify(args: &Args) -> Result<ExitCode, Box<dyn Error>> {
+    let mut archive = open(args)?;
+    let problems = archive.validate()?;
+    for problem in &problems {
+        println!("index: {problem}");
+    }
+
+    let corrupted = archive.verify_all();
+    for err in &corrupted {
+        println!("{}: {} at offset {:#X}", err.name, err.kind, err.offset);
+    }
+
+    if !problems.is_empty() {
+        println!("inconsistent index: {} problems", problems.len());
+    }
+    if !corrupted.is_empty() {
+        println!(
+            "corrupted: {} of {} files",
+            corrupted.len(),
+            archive.entries().len()
+        );
+    }
+
+    if problems.is_empty() && corrupted.is_empty() {
+        println!("ok: {} files", archive.entries().len());
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn extract(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut archive = open(args)?;
+    let report = extract_all(
+        &mut archive,
+        args.positional(2, "dir")?,
+        &ExtractOptions {
+            timestamp: args.flag("timestamp"),
+            include: args.values("include").map(str::to_string).collect(),
+            exclude: args.values("exclude").map(str::to_string).collect(),
+            rewrite_names: args.flag("rewrite-names"),
+        },
+    )?;
+
+    for (index, path, fixes) in &report.rewritten {
+        eprintln!(
+            "rewritten: {:?} -> {} {fixes:?}",
+            archive.entries()[*index].name,
+            path.display()
+        );
+    }
+    for (index, fixes) in &report.rejected {
+        eprintln!("rejected: {:?} {fixes:?}", archive.entries()[*index].name);
+    }
+    for (index, first) in &report.duplicated {
+        eprintln!(
+            "skipped: {:?} has the same path as {:?}",
+            archive.entries()[*index].name,
+            archive.entries()[*first].name
+        );
+    }
+    Ok(())
+}
+
+fn pack(args: &Args) -> Result<(), Box<dyn Error>> {
+    let dir = args.positional(1, "dir")?;
+    let path = args.positional(2, "archive")?;
+
+    let mut writer = new_writer(args, File::create(path)?, None)?;
+    pack_dir(
+        &mut writer,
+        dir,
+        &PackOptions {
+            compression: args.level("compression")?,
+            protected: args.flag("protected"),
+            timestamp: args.flag("timestamp"),
+            include: args.values("include").map(str::to_string).collect(),
+            exclude: args.values("exclude").map(str::to_string).collect(),
+        },
+    )?;
+    finish(args, writer)?;
+    Ok(())
+}
+
+fn add(args: &Args) -> Result<(), Box<dyn Error>> {
+    let files = &args.positional[2..];
+
+    let names = match args.value("name") {
+        Some(name) if files.len() == 1 => vec![args.new_name(name)?],
+        Some(_) => return Err("--name requires a single file".into()),
+        None => files
+            .iter()
+            .map(|file| entry_name(Path::new(file)))
+            .collect::<Result<_, _>>()?,
+    };
+
+    rewrite(
+        args,
+        |name| !names.iter().any(|added| added == name),
+        |args, writer| {
+            let compression = args.level("compression")?;
+            for (name, path) in names.iter().zip(files) {
+                let file = File::open(path)?;
+                let timestamp = if args.flag("timestamp") {
+                    Some(to_timestamp(file.metadata()?.modified()?))
+                } else {
+                    None
+                };
+
+                let mut writer = writer.file(name.clone(), args.flag("protected"), compression)?;
+                writer.timestamp(timestamp);
+                io::copy(&mut BufReader::new(file), &mut writer)?;
+                writer.finish()?;
+            }
+            Ok(())
+        },
+    )
+}
+
+fn remove(args: &Args) -> Result<(), Box<dyn Error>> {
+    let names = &args.positional[2..];
+
+    edit(args, |editor| {
+        for name in names {
+            // Every entry with the name is removed, including duplicates
+            let mut found = false;
+            while let Some(index) = editor.index_of(name) {
+                editor.remove(index);
+                found = true;
+            }
+
+            if !found {
+                return Err(format!("entry not found: {name}").into());
+            }
+        }
+        Ok(())
+    })
+}
+
+fn rename(args: &Args) -> Result<(), Box<dyn Error>> {
+    let name = args.positional(2, "name")?;
+    let new_name = args.new_name(args.positional(3, "new name")?)?;
+
+    edit(args, |editor| {
+        let indices = (0..editor.entries().len())
+            .filter(|&index| !editor.is_removed(index) && editor.entries()[index].name == name)
+            .collect::<Vec<_>>();
+        if indices.is_empty() {
+            return Err(format!("entry not found: {name}").into());
+        }
+
+        for index in indices {
+            editor.rename(index, new_name.clone());
+        }
+        Ok(())
+    })
+}
+
+/// Apply changes of `change` to archive, writing it compacted
+fn edit(
+    args: &Args,
+    change: impl FnOnce(&mut XP3Editor<BufReader<File>>) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let path = args.positional(1, "archive")?;
+    let mut options = XP3ArchiveOptions::new();
+    options.filter(args.filter()?);
+    let mut editor = options.edit(BufReader::new(File::open(path)?))?;
+    change(&mut editor)?;
+
+    // Data of kept files is copied verbatim
+    let (temp, file) = temp_file(Path::new(path))?;
+    let res = (|| {
+        let version = args.version()?.unwrap_or(editor.version);
+        editor.version = version;
+        let mut stream = editor.write_to(BufWriter::new(file), args.level("index-compression")?)?;
+        let end = stream.stream_position()?;
+        let file = stream
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        file.set_len(end)?;
+        Ok::<_, Box<dyn Error>>(())
+    })();
+
+    if let Err(err) = res {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+
+    drop(editor);
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+/// Rewrite archive copying entries accepted by `keep`, then call `extend` to write new entries
+fn rewrite(
+    args: &Args,
+    keep: impl Fn(&str) -> bool,
+    extend: impl FnOnce(&Args, &mut XP3Writer<BufWriter<File>>) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let path = args.positional(1, "archive")?;
+    let mut archive = open(args)?;
+
+    let (temp, file) = temp_file(Path::new(path))?;
+    let res = (|| {
+        let mut writer = new_writer(args, file, Some(archive.version))?;
+        writer.copy_layout(&mut archive);
+
+        // Kept files are copied verbatim
+        for index in 0..archive.entries().len() {
+            if keep(&archive.entries()[index].name) {
+                writer.copy_raw(&mut archive, index).unwrap()?;
+            }
+        }
+
+        extend(args, &mut writer)?;
+        finish(args, writer)
+    })();
+
+    if let Err(err) = res {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+
+    drop(archive);
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+fn new_writer(
+    args: &Args,
+    file: File,
+    version: Option<XP3Version>,
+) -> Result<XP3Writer<BufWriter<File>>, Box<dyn Error>> {
+    let version = args
+        .version()?
+        .or(version)
+        .unwrap_or(XP3Version::Current { minor: 1 });
+
+    let mut writer = XP3Writer::new(version, BufWriter::new(file))?;
+    writer.set_filter(args.filter()?);
+    Ok(writer)
+}
+
+fn finish(args: &Args, writer: XP3Writer<BufWriter<File>>) -> Result<(), Box<dyn Error>> {
+    let mut stream = writer.finish(args.level("index-compression")?)?;
+    let end = stream.stream_position()?;
+    let file = stream
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    file.set_len(end)?;
+    Ok(())
+}
+
+/// Create new temporary file next to `path`, never replacing an existing file
+fn temp_file(path: &Path) -> io::Result<(PathBuf, File)> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut attempt = 0_u32;
+    loop {
+        let temp = dir.join(format!(".{name}.{}-{attempt}.tmp", process::id()));
+        match OpenOptions::new().write(true).create_new(true).open(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Entry name of added file at `path` joined with `/`.
+/// Absolute paths are named relative to the current directory if inside of it, by their file name otherwise.
+fn entry_name(path: &Path) -> Result<String, String> {
+    let relative;
+    let path = if path.has_root() || matches!(path.components().next(), Some(Component::Prefix(_)))
+    {
+        relative = env::current_dir()
+            .ok()
+            .and_then(|dir| Some(path.strip_prefix(dir).ok()?.to_path_buf()))
+            .filter(|relative| {
+                relative
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+            })
+            .or_else(|| path.file_name().map(PathBuf::from))
+            .ok_or_else(|| format!("file has no name: {}", path.display()))?;
+        &relative
+    } else {
+        path
+    };
+
+    let mut name = String::new();
+    for component in path.components() {
+        let part = match component {
+            Component::Normal(part) => part,
+            Component::CurDir => continue,
+            _ => {
+                return Err(format!(
+                    "file is outside of current directory, use --name: {}",
+                    path.display()
+                ));
+            }
+        };
+        let part = part
+            .to_str()
+            .ok_or_else(|| format!("file name is not valid unicode: {}", path.display()))?;
+
+        if !name.is_empty() {
+            name.push('/');
+        }
+        name.push_str(part);
+    }
+
+    if name.is_empty() {
+        return Err(format!("file has no name: {}", path.display()));
+    }
+    Ok(name)
+}
+
+fn entry_json(entry: &XP3FileEntry) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+    format!(
+        "{{\"name\":{},\"hashed_name\":{},\"size\":{},\"archive_size\":{},\"checksum\":{},\"protected\":{},\"timestamp\":{}}}",
+        json_string(&entry.name),
+        optional(entry.hashed_name.as_deref().map(json_string)),
+        entry.size,
+        entry.archive_size,
+        entry.checksum,
+        entry.protected,
+        optional(entry.timestamp.map(|timestamp| timestamp.to_string())),
+    )
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
@@ -0,0 +1,114 @@
+//! Common public encryption schemes used by Kirikiri games
+
+use std::sync::Arc;
+
+use crate::{entry::XP3FileEntry, filter::XP3Filter};
+
+/// Names of the schemes, accepted by [`by_name`]
+pub const SCHEMES: &[&str] = &["xor", "checksum-xor", "key-xor", "table-xor"];
+
+/// Create filter of a scheme by name.
+///
+/// * `xor`: [`Xor`], `key` must be a single byte.
+/// * `checksum-xor`: [`ChecksumXor`], `key` must be empty.
+/// * `key-xor`: [`KeyXor`], `key` must not be empty.
+/// * `table-xor`: [`TableXor`], `key` must not be empty.
+///
+/// Returns [`None`] if the scheme is unknown or `key` is invalid for it.
+pub fn by_name(name: &str, key: &[u8]) -> Option<Arc<dyn XP3Filter>> {
+    Some(match (name, key) {
+        ("xor", &[key]) => Arc::new(Xor(key)),
+        ("checksum-xor", []) => Arc::new(ChecksumXor),
+        ("key-xor", _) => Arc::new(KeyXor::new(key.to_vec())?),
+        ("table-xor", _) => Arc::new(TableXor::new(key.to_vec())?),
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Xor every byte with a fixed single byte key
+pub struct Xor(pub u8);
+
+impl XP3Filter for Xor {
+    fn decrypt(&self, _: &XP3FileEntry, _: u64, buf: &mut [u8]) {
+        xor(buf, self.0);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Xor every byte with the lowest byte of file adler32 checksum
+pub struct ChecksumXor;
+
+impl XP3Filter for ChecksumXor {
+    fn decrypt(&self, entry: &XP3FileEntry, _: u64, buf: &mut [u8]) {
+        xor(buf, entry.checksum as u8);
+    }
+
+    fn needs_checksum(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Xor bytes with a fixed key repeated from the start of file
+pub struct KeyXor(Vec<u8>);
+
+impl KeyXor {
+    /// Create filter with `key`.
+    /// Returns [`None`] if `key` is empty.
+    pub fn new(key: Vec<u8>) -> Option<Self> {
+        (!key.is_empty()).then_some(Self(key))
+    }
+
+    #[inline]
+    pub fn key(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl XP3Filter for KeyXor {
+    fn decrypt(&self, _: &XP3FileEntry, offset: u64, buf: &mut [u8]) {
+        xor_table(buf, &self.0, offset);
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Xor bytes with a table, starting from the position keyed by file adler32 checksum
+pub struct TableXor(Vec<u8>);
+
+impl TableXor {
+    /// Create filter with `table`.
+    /// Returns [`None`] if `table` is empty.
+    pub fn new(table: Vec<u8>) -> Option<Self> {
+        (!table.is_empty()).then_some(Self(table))
+    }
+
+    #[inline]
+    pub fn table(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl XP3Filter for TableXor {
+    fn decrypt(&self, entry: &XP3FileEntry, offset: u64, buf: &mut [u8]) {
+        xor_table(buf, &self.0, offset.wrapping_add(entry.checksum as u64));
+    }
+
+    fn needs_checksum(&self) -> bool {
+        true
+    }
+}
+
+fn xor(buf: &mut [u8], key: u8) {
+    for b in buf {
+        *b ^= key;
+    }
+}
+
+/// Xor `buf` with non empty `table` repeated, starting from `offset` of it
+fn xor_table(buf: &mut [u8], table: &[u8], offset: u64) {
+    let start = (offset % table.len() as u64) as usize;
+    for (b, key) in buf.iter_mut().zip(table.iter().cycle().skip(start)) {
+        *b ^= key;
+    }
+}
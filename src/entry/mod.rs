@@ -1,23 +1,69 @@
 mod read;
+mod validate;
 mod write;
 
+pub(crate) use validate::IndexNote;
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io::{self, ErrorKind, SeekFrom},
+};
+
+use crate::XP3_INDEX_HNFN_IDENTIFIER;
+
 /// FileIndex for xp3 archive.
 /// Contains information about file and data offsets.
 #[derive(Debug, Clone, Default)]
 pub struct XP3FileEntry {
     pub protected: bool,
     pub name: String,
+    /// Hashed name stored in file index if the real name is resolved from `hnfn` or `eliF` table
+    pub hashed_name: Option<String>,
     pub size: u64,
     pub archive_size: u64,
     pub checksum: u32,
     pub timestamp: Option<u64>,
+    /// Unknown chunks inside of file index, kept as is
+    pub unknown_chunks: Vec<XP3Chunk>,
+    /// Tags of chunks inside of file index in their original order if it has unknown chunks.
+    /// Unknown chunks are written back at their positions, or after `adlr` chunk if empty
+    pub chunk_order: Vec<u32>,
+}
+
+/// Raw index chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XP3Chunk {
+    pub tag: u32,
+    pub data: Vec<u8>,
+}
+
+/// Kind of top level index chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum IndexChunk {
+    File,
+    Name,
+    Unknown,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub(super) struct XP3Entries {
     pub entries: Vec<XP3FileEntry>,
     pub file_starts: Vec<usize>,
     pub segments: Vec<DataSegment>,
+    /// Unknown top level index chunks
+    pub chunks: Vec<XP3Chunk>,
+    /// Order of top level chunks if index has unknown chunks
+    pub layout: Vec<IndexChunk>,
+    /// Tag of real name chunks, `hnfn` or `eliF`
+    pub name_tag: u32,
+    /// Inconsistencies tolerated while parsing index
+    pub notes: Vec<IndexNote>,
+}
+
+impl Default for XP3Entries {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl XP3Entries {
@@ -26,8 +72,110 @@ impl XP3Entries {
             entries: vec![],
             file_starts: vec![],
             segments: vec![],
+            chunks: vec![],
+            layout: vec![],
+            name_tag: XP3_INDEX_HNFN_IDENTIFIER,
+            notes: vec![],
         }
     }
+
+    /// Empty index keeping unknown top level chunks, their order and name chunk tag
+    pub fn layout(&self) -> Self {
+        Self {
+            chunks: self.chunks.clone(),
+            layout: self.layout.clone(),
+            name_tag: self.name_tag,
+            ..Self::new()
+        }
+    }
+
+    /// Add file with its data segments.
+    /// Returns file index
+    pub fn push(
+        &mut self,
+        entry: XP3FileEntry,
+        segments: impl IntoIterator<Item = DataSegment>,
+    ) -> usize {
+        let start_segment = self.segments.len();
+        for segment in segments {
+            let id = self.segments.len();
+            if id > start_segment {
+                self.segments[id - 1].next = Some(id);
+            }
+
+            self.segments.push(DataSegment {
+                next: None,
+                ..segment
+            });
+        }
+
+        let id = self.entries.len();
+        self.entries.push(entry);
+        self.file_starts.push(start_segment);
+        id
+    }
+
+    /// End of file data relative to the archive start
+    pub fn data_end(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.start.saturating_add(segment.archive_size))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Add file sharing data segments of file at `index`.
+    /// Returns file index
+    pub fn push_shared(&mut self, entry: XP3FileEntry, index: usize) -> usize {
+        let id = self.entries.len();
+        self.entries.push(XP3FileEntry {
+            archive_size: self.entries[index].archive_size,
+            ..entry
+        });
+        self.file_starts.push(self.file_starts[index]);
+        id
+    }
+}
+
+/// Index of written file contents used to deduplicate files
+#[derive(Debug, Clone, Default)]
+pub(super) struct Dedup {
+    /// Maps size and SHA-256 digest of stored data to file index
+    files: HashMap<(u64, [u8; 32]), usize>,
+    saved: u64,
+}
+
+impl Dedup {
+    /// Find file with identical content.
+    /// Records `index` as the first file of the content if not found.
+    /// Content is compared by a cryptographic digest, so distinct data is never shared.
+    pub fn find_or_insert(&mut self, size: u64, digest: [u8; 32], index: usize) -> Option<usize> {
+        match self.files.entry((size, digest)) {
+            Entry::Occupied(entry) => Some(*entry.get()),
+            Entry::Vacant(entry) => {
+                entry.insert(index);
+                None
+            }
+        }
+    }
+
+    #[inline]
+    /// Find file with identical content without recording it
+    pub fn find(&self, size: u64, digest: &[u8; 32]) -> Option<usize> {
+        self.files.get(&(size, *digest)).copied()
+    }
+
+    #[inline]
+    /// Add bytes saved by deduplication
+    pub fn add_saved(&mut self, size: u64) {
+        self.saved += size;
+    }
+
+    #[inline]
+    /// Total bytes saved by deduplication
+    pub const fn saved(&self) -> u64 {
+        self.saved
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -38,3 +186,64 @@ pub(super) struct DataSegment {
     pub archive_size: u64,
     pub next: Option<usize>,
 }
+
+impl DataSegment {
+    /// Stream position of `offset` inside of the segment in archive at `start`
+    pub fn position(&self, start: u64, offset: u64) -> io::Result<u64> {
+        start
+            .checked_add(self.start)
+            .and_then(|pos| pos.checked_add(offset))
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "segment offset out of range"))
+    }
+
+    /// Size of a file starting from `start`
+    pub fn file_size(segments: &[DataSegment], start: usize) -> u64 {
+        DataSegment::chain(segments, start)
+            .fold(0, |size, (_, segment, _)| size.saturating_add(segment.size))
+    }
+
+    /// Iterate segments of a file starting from `start`.
+    /// Yields segment index, segment and file offset where the segment begins.
+    pub fn chain(
+        segments: &[DataSegment],
+        start: usize,
+    ) -> impl Iterator<Item = (usize, DataSegment, u64)> + '_ {
+        let mut next = Some(start);
+        let mut offset = 0_u64;
+        core::iter::from_fn(move || {
+            let index = next?;
+            let segment = segments[index];
+            next = segment.next;
+
+            let segment_offset = offset;
+            offset = offset.saturating_add(segment.size);
+            Some((index, segment, segment_offset))
+        })
+    }
+
+    /// Find segment containing `offset` of a file starting from `start`.
+    /// Returns segment index and file offset where the segment begins.
+    pub fn find(segments: &[DataSegment], start: usize, offset: u64) -> Option<(usize, u64)> {
+        DataSegment::chain(segments, start)
+            .find(|(_, segment, segment_offset)| {
+                offset < segment_offset.saturating_add(segment.size)
+            })
+            .map(|(index, _, segment_offset)| (index, segment_offset))
+    }
+}
+
+/// Compute absolute file offset of a seek
+pub(crate) fn seek_offset(pos: u64, size: u64, seek: SeekFrom) -> io::Result<u64> {
+    let offset = match seek {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::End(offset) => size.checked_add_signed(offset),
+        SeekFrom::Current(offset) => pos.checked_add_signed(offset),
+    };
+
+    offset.ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
@@ -1,114 +1,327 @@
-use std::io::{self, Cursor, ErrorKind};
+use core::mem;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+};
 
-use async_compression::tokio::bufread::ZlibDecoder;
 use byteorder::{LittleEndian, ReadBytesExt};
-use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use flate2::read::ZlibDecoder;
 
 use crate::{
-    XP3_INDEX_ADLR_IDENTIFIER, XP3_INDEX_FILE_IDENTIFIER, XP3_INDEX_INFO_IDENTIFIER,
-    XP3_INDEX_SEGM_IDENTIFIER, XP3_INDEX_TIME_IDENTIFIER, XP3_PROTECTED_FLAG,
-    entry::{DataSegment, XP3Entries, XP3FileEntry},
-    read::error::XP3OpenError,
+    XP3_INDEX_ADLR_IDENTIFIER, XP3_INDEX_ELIF_IDENTIFIER, XP3_INDEX_FILE_IDENTIFIER,
+    XP3_INDEX_HNFN_IDENTIFIER, XP3_INDEX_INFO_IDENTIFIER, XP3_INDEX_SEGM_IDENTIFIER,
+    XP3_INDEX_TIME_IDENTIFIER, XP3_PROTECTED_FLAG,
+    entry::{DataSegment, IndexChunk, IndexNote, XP3Chunk, XP3Entries, XP3FileEntry},
+    error::{IndexLocation, XP3OpenError},
+    limits::{XP3LimitKind, XP3Limits},
 };
 
 impl XP3Entries {
-    pub async fn open(stream: &mut (impl AsyncRead + Unpin)) -> Result<Self, XP3OpenError> {
+    pub fn open(stream: &mut impl Read, limits: &XP3Limits) -> Result<Self, XP3OpenError> {
+        let compressed = stream.read_u8()? != 0;
+        let size = stream.read_u64::<LittleEndian>()?;
+        let original_size = if compressed {
+            Some(stream.read_u64::<LittleEndian>()?)
+        } else {
+            None
+        };
+        check_index_size(size, original_size, limits)?;
+
+        let mut data = vec![];
+        stream.take(size).read_to_end(&mut data)?;
+        Self::parse(&data, size, original_size, limits)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub async fn open_async(
+        stream: &mut (impl tokio::io::AsyncRead + Unpin),
+        limits: &XP3Limits,
+    ) -> Result<Self, XP3OpenError> {
+        use tokio::io::AsyncReadExt;
+
         let compressed = stream.read_u8().await? != 0;
         let size = stream.read_u64_le().await?;
-
-        let entries = Self::default();
-        Ok(if compressed {
-            let original_size = stream.read_u64_le().await?;
-            entries
-                .open_inner(
-                    ZlibDecoder::new(BufReader::new(stream.take(size))),
-                    original_size,
-                )
-                .await?
+        let original_size = if compressed {
+            Some(stream.read_u64_le().await?)
         } else {
-            entries.open_inner(stream.take(size), size).await?
-        })
+            None
+        };
+        check_index_size(size, original_size, limits)?;
+
+        let mut data = vec![];
+        stream.take(size).read_to_end(&mut data).await?;
+        Self::parse(&data, size, original_size, limits)
     }
 
-    async fn open_inner(
-        mut self,
-        mut stream: impl AsyncRead + Unpin,
-        original_size: u64,
+    /// Parse index data read from archive.
+    /// `size` is the stored size of the index, `original_size` is present if the index is compressed.
+    fn parse(
+        data: &[u8],
+        size: u64,
+        original_size: Option<u64>,
+        limits: &XP3Limits,
     ) -> Result<Self, XP3OpenError> {
-        let mut read = 0;
-        let mut buf = vec![];
-        while read < original_size {
-            let (key, index_size) = read_index(&mut stream).await?;
-            if read + index_size > original_size {
-                return Err(XP3OpenError::Io(ErrorKind::UnexpectedEof.into()));
+        if (data.len() as u64) < size {
+            return Err(XP3OpenError::TruncatedIndex {
+                expected: size,
+                actual: data.len() as _,
+            });
+        }
+
+        let entries = Self::default();
+        match original_size {
+            Some(original_size) => {
+                let mut buf = vec![];
+                ZlibDecoder::new(data)
+                    .take(original_size)
+                    .read_to_end(&mut buf)?;
+                if (buf.len() as u64) < original_size {
+                    return Err(XP3OpenError::TruncatedIndex {
+                        expected: original_size,
+                        actual: buf.len() as _,
+                    });
+                }
+
+                entries.parse_inner(&buf, limits)
+            }
+
+            None => entries.parse_inner(data, limits),
+        }
+    }
+
+    fn parse_inner(mut self, data: &[u8], limits: &XP3Limits) -> Result<Self, XP3OpenError> {
+        self.parse_chunks(data, limits, false)?;
+        Ok(self)
+    }
+
+    /// Read index keeping entries parsed before damage.
+    /// Returns entries and the first error found
+    pub fn open_lenient(
+        stream: &mut impl Read,
+        limits: &XP3Limits,
+    ) -> (Self, Option<XP3OpenError>) {
+        let mut entries = Self::default();
+        let error = entries.read_lenient(stream, limits).err();
+        (entries, error)
+    }
+
+    fn read_lenient(
+        &mut self,
+        stream: &mut impl Read,
+        limits: &XP3Limits,
+    ) -> Result<(), XP3OpenError> {
+        let compressed = stream.read_u8()? != 0;
+        let size = stream.read_u64::<LittleEndian>()?;
+        let original_size = if compressed {
+            Some(stream.read_u64::<LittleEndian>()?)
+        } else {
+            None
+        };
+        check_index_size(size, original_size, limits)?;
+
+        // Bytes read before an error are kept
+        let mut data = vec![];
+        let mut error = match stream.take(size).read_to_end(&mut data) {
+            Ok(_) if (data.len() as u64) < size => Some(XP3OpenError::TruncatedIndex {
+                expected: size,
+                actual: data.len() as _,
+            }),
+            Ok(_) => None,
+            Err(err) => Some(err.into()),
+        };
+
+        if let Some(original_size) = original_size {
+            let mut buf = vec![];
+            let res = ZlibDecoder::new(&data[..])
+                .take(original_size)
+                .read_to_end(&mut buf);
+            match res {
+                Ok(_) if (buf.len() as u64) < original_size => {
+                    error.get_or_insert(XP3OpenError::TruncatedIndex {
+                        expected: original_size,
+                        actual: buf.len() as _,
+                    });
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    error.get_or_insert(err.into());
+                }
             }
-            (&mut stream).take(index_size).read_to_end(&mut buf).await?;
+            data = buf;
+        }
 
-            match key {
+        let res = self.parse_chunks(&data, limits, true);
+        match error {
+            Some(err) => Err(err),
+            None => res,
+        }
+    }
+
+    /// Parse top level chunks of index data.
+    /// In lenient mode malformed `File` and name chunks are skipped and parsing stops at a truncated chunk,
+    /// returning the first error after resolving names.
+    fn parse_chunks(
+        &mut self,
+        data: &[u8],
+        limits: &XP3Limits,
+        lenient: bool,
+    ) -> Result<(), XP3OpenError> {
+        let mut names = HashMap::<u32, VecDeque<String>>::new();
+        let mut layout = vec![];
+        let mut error = None;
+        let mut chunks = Chunks::new(data, 0);
+        while let Some(chunk) = chunks.next() {
+            let (tag, offset, buf) = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) if lenient => {
+                    error.get_or_insert(err.into_error("", None, None));
+                    break;
+                }
+                Err(err) => return Err(err.into_error("", None, None)),
+            };
+
+            let res = match tag {
                 XP3_INDEX_FILE_IDENTIFIER => {
-                    self.read_file_index(&buf).await?;
+                    let (segments, notes) = (self.segments.len(), self.notes.len());
+                    let res = self.read_file_index(offset, buf, limits);
+                    match res {
+                        Ok(()) => layout.push(IndexChunk::File),
+                        Err(_) => {
+                            self.segments.truncate(segments);
+                            self.notes.truncate(notes);
+                        }
+                    }
+                    res
+                }
+
+                XP3_INDEX_HNFN_IDENTIFIER | XP3_INDEX_ELIF_IDENTIFIER => {
+                    read_name_chunk(tag, offset, buf, limits).map(|(checksum, name)| {
+                        names.entry(checksum).or_default().push_back(name);
+                        layout.push(IndexChunk::Name);
+                        self.name_tag = tag;
+                    })
                 }
 
                 _ => {
-                    // Unknown identifier
+                    self.chunks.push(XP3Chunk {
+                        tag,
+                        data: buf.to_vec(),
+                    });
+                    layout.push(IndexChunk::Unknown);
+                    Ok(())
                 }
+            };
+
+            match res {
+                Ok(()) => {}
+                Err(err) if lenient => {
+                    error.get_or_insert(err);
+                }
+                Err(err) => return Err(err),
             }
-            buf.clear();
-            read += index_size + 12;
         }
 
-        Ok(self)
+        if !self.chunks.is_empty() {
+            self.layout = layout;
+        }
+
+        // Resolve real names of hashed entries.
+        // Names sharing a checksum belong to entries with identical content in the same order.
+        if !names.is_empty() {
+            for entry in &mut self.entries {
+                if let Some(name) = names.get_mut(&entry.checksum).and_then(VecDeque::pop_front) {
+                    entry.hashed_name = Some(mem::replace(&mut entry.name, name));
+                }
+            }
+        }
+
+        error.map_or(Ok(()), Err)
     }
 
-    async fn read_file_index(&mut self, data: &[u8]) -> Result<(), XP3OpenError> {
-        let total_size = data.len() as u64;
-        let mut cursor = Cursor::new(data);
+    /// Read `File` chunk at `offset` of index
+    fn read_file_index(
+        &mut self,
+        offset: u64,
+        data: &[u8],
+        limits: &XP3Limits,
+    ) -> Result<(), XP3OpenError> {
+        let index = self.entries.len();
+        if index >= limits.max_entries {
+            return Err(XP3OpenError::LimitExceeded {
+                kind: XP3LimitKind::Entries,
+                limit: limits.max_entries as _,
+                actual: index as u64 + 1,
+            });
+        }
 
         let mut entry = XP3FileEntry::default();
+        let mut has_name = false;
+        let mut has_checksum = false;
+        let location = |entry: &XP3FileEntry, has_name: bool, offset, path: String| IndexLocation {
+            offset,
+            path,
+            entry: Some(index),
+            name: has_name.then(|| entry.name.clone()),
+        };
+
         let mut start_segment_index: Option<usize> = None;
         let mut prev_segment_index: Option<usize> = None;
-        while cursor.position() < total_size {
-            let (key, index_size) = read_index(&mut cursor).await?;
-            if cursor.position() + index_size > total_size {
-                return Err(XP3OpenError::Io(ErrorKind::UnexpectedEof.into()));
-            }
+        let mut order = vec![];
+        let mut chunks = Chunks::new(data, offset + 12);
+        while let Some(chunk) = chunks.next() {
+            let (tag, chunk_offset, buf) = chunk.map_err(|err| {
+                let name = has_name.then_some(entry.name.as_str());
+                err.into_error("File", Some(index), name)
+            })?;
+            order.push(tag);
+            let truncated =
+                |entry: &XP3FileEntry, has_name, expected| XP3OpenError::TruncatedChunk {
+                    location: location(
+                        entry,
+                        has_name,
+                        chunk_offset,
+                        format!("File/{}", tag_name(tag)),
+                    ),
+                    expected,
+                    actual: buf.len() as _,
+                };
 
-            let mut sub_data =
-                Cursor::new(&cursor.get_ref()[cursor.position() as usize..][..index_size as usize]);
-            match key {
+            match tag {
                 XP3_INDEX_INFO_IDENTIFIER => {
-                    entry.protected = ReadBytesExt::read_u32::<LittleEndian>(&mut sub_data)?
-                        == XP3_PROTECTED_FLAG;
-                    entry.size = ReadBytesExt::read_u64::<LittleEndian>(&mut sub_data)?;
-                    entry.archive_size = ReadBytesExt::read_u64::<LittleEndian>(&mut sub_data)?;
-
-                    let name_len = ReadBytesExt::read_u16::<LittleEndian>(&mut sub_data)?;
-                    entry.name = char::decode_utf16(
-                        sub_data.get_ref()[sub_data.position() as usize..][..name_len as usize * 2]
-                            .chunks_exact(2)
-                            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]])),
-                    )
-                    .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
-                    .collect();
+                    let (Some(flags), Some(size), Some(archive_size)) =
+                        (read_u32(buf, 0), read_u64(buf, 4), read_u64(buf, 12))
+                    else {
+                        return Err(truncated(&entry, has_name, 22));
+                    };
+                    // Other bits of flags are reserved
+                    entry.protected = flags & XP3_PROTECTED_FLAG != 0;
+                    entry.size = size;
+                    entry.archive_size = archive_size;
+
+                    entry.name = read_name(&buf[20..], limits, |expected| {
+                        truncated(&entry, has_name, 20 + expected)
+                    })?;
+                    has_name = true;
                 }
 
                 XP3_INDEX_SEGM_IDENTIFIER => {
-                    let count = sub_data.get_ref().len() / 28;
-                    for _ in 0..count {
-                        let id = self.segments.len();
+                    if !buf.len().is_multiple_of(28) {
+                        self.notes
+                            .push(IndexNote::SegmentChunkLength(index, buf.len() as _));
+                    }
 
-                        let compressed =
-                            ReadBytesExt::read_u32::<LittleEndian>(&mut sub_data)? != 0;
-                        let start = ReadBytesExt::read_u64::<LittleEndian>(&mut sub_data)?;
-                        let size = ReadBytesExt::read_u64::<LittleEndian>(&mut sub_data)?;
-                        let archive_size = ReadBytesExt::read_u64::<LittleEndian>(&mut sub_data)?;
-                        self.segments.push(DataSegment {
-                            compressed,
-                            start,
-                            size,
-                            archive_size,
+                    for segment in buf.chunks_exact(28) {
+                        let segment = DataSegment {
+                            compressed: read_u32(segment, 0).unwrap() != 0,
+                            start: read_u64(segment, 4).unwrap(),
+                            size: read_u64(segment, 12).unwrap(),
+                            archive_size: read_u64(segment, 20).unwrap(),
                             next: None,
-                        });
+                        };
+                        check_segment_ratio(&segment, limits)?;
+
+                        let id = self.segments.len();
+                        self.segments.push(segment);
 
                         if start_segment_index.is_none() {
                             start_segment_index = Some(id);
@@ -120,25 +333,42 @@ impl XP3Entries {
                 }
 
                 XP3_INDEX_ADLR_IDENTIFIER => {
-                    entry.checksum = ReadBytesExt::read_u32::<LittleEndian>(&mut sub_data)?;
+                    entry.checksum =
+                        read_u32(buf, 0).ok_or_else(|| truncated(&entry, has_name, 4))?;
+                    has_checksum = true;
                 }
 
                 XP3_INDEX_TIME_IDENTIFIER => {
-                    entry.timestamp = Some(ReadBytesExt::read_u64::<LittleEndian>(&mut sub_data)?);
+                    entry.timestamp =
+                        Some(read_u64(buf, 0).ok_or_else(|| truncated(&entry, has_name, 8))?);
                 }
 
                 _ => {
-                    // SKIP sections we don't know yet.
+                    entry.unknown_chunks.push(XP3Chunk {
+                        tag,
+                        data: buf.to_vec(),
+                    });
                 }
             }
-
-            cursor.set_position(cursor.position() + index_size);
         }
 
         let Some(start_segment_index) = start_segment_index else {
-            return Err(XP3OpenError::InvalidSection(XP3_INDEX_SEGM_IDENTIFIER));
+            return Err(XP3OpenError::MissingChunk {
+                location: location(&entry, has_name, offset, "File/segm".to_string()),
+            });
         };
 
+        if !has_name {
+            self.notes.push(IndexNote::MissingInfo(index));
+        }
+        if !has_checksum {
+            self.notes.push(IndexNote::MissingChecksum(index));
+        }
+
+        if !entry.unknown_chunks.is_empty() {
+            entry.chunk_order = order;
+        }
+
         self.entries.push(entry);
         self.file_starts.push(start_segment_index);
 
@@ -146,7 +376,210 @@ impl XP3Entries {
     }
 }
 
-// Read index key and size
-async fn read_index(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<(u32, u64)> {
-    Ok((stream.read_u32_le().await?, stream.read_u64_le().await?))
+/// Tag, offset in index and data of a chunk
+type Chunk<'a> = (u32, u64, &'a [u8]);
+
+/// Iterator over chunks in data of index or its parent chunk
+struct Chunks<'a> {
+    data: &'a [u8],
+    /// Offset of `data` in index
+    offset: u64,
+}
+
+impl<'a> Chunks<'a> {
+    #[inline]
+    const fn new(data: &'a [u8], offset: u64) -> Self {
+        Self { data, offset }
+    }
+
+    fn next(&mut self) -> Option<Result<Chunk<'a>, Truncated>> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let tag = read_u32(self.data, 0);
+        let (Some(tag), Some(size)) = (tag, read_u64(self.data, 4)) else {
+            return Some(Err(self.truncated(tag, 12, self.data.len())));
+        };
+
+        let rest = &self.data[12..];
+        let Some(data) = usize::try_from(size).ok().and_then(|size| rest.get(..size)) else {
+            return Some(Err(self.truncated(Some(tag), size, rest.len())));
+        };
+
+        let offset = self.offset;
+        self.data = &rest[data.len()..];
+        self.offset += 12 + size;
+        Some(Ok((tag, offset, data)))
+    }
+
+    fn truncated(&mut self, tag: Option<u32>, expected: u64, actual: usize) -> Truncated {
+        let offset = self.offset;
+        self.data = &[];
+        Truncated {
+            tag,
+            offset,
+            expected,
+            actual: actual as _,
+        }
+    }
+}
+
+/// Chunk extending past the data containing it
+struct Truncated {
+    /// Tag of the chunk if readable
+    tag: Option<u32>,
+    offset: u64,
+    expected: u64,
+    actual: u64,
+}
+
+impl Truncated {
+    fn into_error(self, parent: &str, entry: Option<usize>, name: Option<&str>) -> XP3OpenError {
+        let tag = self.tag.map_or_else(|| "?".to_string(), tag_name);
+        XP3OpenError::TruncatedChunk {
+            location: IndexLocation {
+                offset: self.offset,
+                path: if parent.is_empty() {
+                    tag
+                } else {
+                    format!("{parent}/{tag}")
+                },
+                entry,
+                name: name.map(str::to_string),
+            },
+            expected: self.expected,
+            actual: self.actual,
+        }
+    }
+}
+
+/// Read `hnfn` or `eliF` chunk at `offset` of index.
+/// Returns checksum and name of the entry
+fn read_name_chunk(
+    tag: u32,
+    offset: u64,
+    data: &[u8],
+    limits: &XP3Limits,
+) -> Result<(u32, String), XP3OpenError> {
+    let truncated = |expected| XP3OpenError::TruncatedChunk {
+        location: IndexLocation {
+            offset,
+            path: tag_name(tag),
+            ..Default::default()
+        },
+        expected,
+        actual: data.len() as _,
+    };
+
+    let checksum = read_u32(data, 0).ok_or_else(|| truncated(6))?;
+    let name = read_name(&data[4..], limits, |expected| truncated(4 + expected))?;
+    Ok((checksum, name))
+}
+
+/// Check stored and decompressed index size against limits
+fn check_index_size(
+    size: u64,
+    original_size: Option<u64>,
+    limits: &XP3Limits,
+) -> Result<(), XP3OpenError> {
+    let exceeded = |kind, limit, actual| XP3OpenError::LimitExceeded {
+        kind,
+        limit,
+        actual,
+    };
+
+    let largest = original_size.map_or(size, |original_size| original_size.max(size));
+    if largest > limits.max_index_size {
+        return Err(exceeded(
+            XP3LimitKind::IndexSize,
+            limits.max_index_size,
+            largest,
+        ));
+    }
+
+    if let Some(original_size) = original_size
+        && original_size > size.saturating_mul(limits.max_compression_ratio)
+    {
+        return Err(exceeded(
+            XP3LimitKind::CompressionRatio,
+            limits.max_compression_ratio,
+            original_size.div_ceil(size.max(1)),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check decompressed size of compressed segment against limits
+fn check_segment_ratio(segment: &DataSegment, limits: &XP3Limits) -> Result<(), XP3OpenError> {
+    if segment.compressed
+        && segment.size
+            > segment
+                .archive_size
+                .saturating_mul(limits.max_segment_compression_ratio)
+    {
+        return Err(XP3OpenError::LimitExceeded {
+            kind: XP3LimitKind::SegmentCompressionRatio,
+            limit: limits.max_segment_compression_ratio,
+            actual: segment.size.div_ceil(segment.archive_size.max(1)),
+        });
+    }
+
+    Ok(())
+}
+
+/// Readable name of chunk tag, hex if it is not ascii
+pub(crate) fn tag_name(tag: u32) -> String {
+    let bytes = tag.to_le_bytes();
+    if bytes.iter().all(u8::is_ascii_graphic) {
+        bytes.iter().map(|&b| b as char).collect()
+    } else {
+        format!("{tag:#010X}")
+    }
+}
+
+#[inline]
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+#[inline]
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().unwrap(),
+    ))
+}
+
+/// Read length prefixed utf-16 name.
+/// `truncated` creates error from the size required if data is too short
+fn read_name(
+    data: &[u8],
+    limits: &XP3Limits,
+    truncated: impl FnOnce(u64) -> XP3OpenError,
+) -> Result<String, XP3OpenError> {
+    let Some(len) = data.get(..2) else {
+        return Err(truncated(2));
+    };
+    let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+    if len > limits.max_name_len {
+        return Err(XP3OpenError::LimitExceeded {
+            kind: XP3LimitKind::NameLength,
+            limit: limits.max_name_len as _,
+            actual: len as _,
+        });
+    }
+
+    let Some(data) = data.get(2..2 + len * 2) else {
+        return Err(truncated(2 + len as u64 * 2));
+    };
+
+    Ok(char::decode_utf16(
+        data.chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]])),
+    )
+    .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
+    .collect())
 }
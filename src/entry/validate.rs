@@ -0,0 +1,117 @@
+use crate::{
+    entry::{DataSegment, XP3Entries},
+    error::XP3Problem,
+};
+
+/// Inconsistency tolerated while parsing index
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum IndexNote {
+    /// Entry without `info` chunk
+    MissingInfo(usize),
+    /// Entry and length of its `segm` chunk not a multiple of 28 bytes
+    SegmentChunkLength(usize, u64),
+    /// Entry without `adlr` chunk
+    MissingChecksum(usize),
+}
+
+impl XP3Entries {
+    /// Check index consistency against stream of `len` bytes with archive at `start`.
+    /// Returns every problem found
+    pub fn validate(&self, start: u64, len: u64) -> Vec<XP3Problem> {
+        let name = |entry: usize| self.entries[entry].name.clone();
+
+        let mut problems = self
+            .notes
+            .iter()
+            .map(|&note| match note {
+                IndexNote::MissingInfo(entry) => XP3Problem::MissingInfo { entry },
+                IndexNote::SegmentChunkLength(entry, length) => XP3Problem::SegmentChunkLength {
+                    entry,
+                    name: name(entry),
+                    length,
+                },
+                IndexNote::MissingChecksum(entry) => XP3Problem::MissingChecksum {
+                    entry,
+                    name: name(entry),
+                },
+            })
+            .collect::<Vec<_>>();
+
+        // Data ranges of segments with their entry and index in the file
+        let mut ranges = vec![];
+        for (entry, (file, &file_start)) in self.entries.iter().zip(&self.file_starts).enumerate() {
+            let (mut size, mut archive_size) = (0_u64, 0_u64);
+            for (segment, (_, data, _)) in
+                DataSegment::chain(&self.segments, file_start).enumerate()
+            {
+                size = size.saturating_add(data.size);
+                archive_size = archive_size.saturating_add(data.archive_size);
+
+                let end = start
+                    .saturating_add(data.start)
+                    .saturating_add(data.archive_size);
+                if end > len {
+                    problems.push(XP3Problem::SegmentPastEnd {
+                        entry,
+                        name: name(entry),
+                        segment,
+                        end,
+                        len,
+                    });
+                }
+
+                if data.archive_size > 0 {
+                    ranges.push((
+                        data.start,
+                        data.start.saturating_add(data.archive_size),
+                        entry,
+                        segment,
+                    ));
+                }
+            }
+
+            if size != file.size {
+                problems.push(XP3Problem::SizeMismatch {
+                    entry,
+                    name: name(entry),
+                    info: file.size,
+                    segments: size,
+                });
+            }
+            if archive_size != file.archive_size {
+                problems.push(XP3Problem::ArchiveSizeMismatch {
+                    entry,
+                    name: name(entry),
+                    info: file.archive_size,
+                    segments: archive_size,
+                });
+            }
+        }
+
+        // Compare each range with the previous range reaching furthest
+        ranges.sort_unstable();
+        let mut furthest: Option<(u64, u64, usize, usize)> = None;
+        for range in ranges {
+            let (range_start, range_end, entry, segment) = range;
+            if let Some((other_start, other_end, other_entry, other_segment)) = furthest
+                && range_start < other_end
+                && (range_start, range_end) != (other_start, other_end)
+            {
+                problems.push(XP3Problem::OverlappingSegments {
+                    entry,
+                    name: name(entry),
+                    segment,
+                    other_entry,
+                    other_name: name(other_entry),
+                    other_segment,
+                });
+            }
+
+            if furthest.is_none_or(|(_, end, ..)| range_end > end) {
+                furthest = Some(range);
+            }
+        }
+
+        problems
+    }
+}
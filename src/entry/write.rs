@@ -1,66 +1,160 @@
-use std::io::{self, Write};
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
 
 use byteorder::{LittleEndian, WriteBytesExt};
 use flate2::{Compression, write::ZlibEncoder};
-use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 use crate::{
     XP3_INDEX_ADLR_IDENTIFIER, XP3_INDEX_FILE_IDENTIFIER, XP3_INDEX_INFO_IDENTIFIER,
-    XP3_INDEX_SEGM_IDENTIFIER, XP3_INDEX_TIME_IDENTIFIER,
-    entry::{DataSegment, XP3Entries, XP3FileEntry},
+    XP3_INDEX_SEGM_IDENTIFIER, XP3_INDEX_TIME_IDENTIFIER, XP3_PROTECTED_FLAG,
+    entry::{DataSegment, IndexChunk, XP3Chunk, XP3Entries, XP3FileEntry},
 };
 
 impl XP3Entries {
-    pub async fn write(
-        &self,
-        compression: Option<u8>,
-        stream: &mut (impl AsyncWrite + Unpin),
-    ) -> io::Result<()> {
-        let buf = if let Some(level) = compression {
-            stream.write_u8(1).await?;
-
+    /// Encode index to be written at the end of archive
+    pub fn encode(&self, compression: Option<u8>) -> io::Result<Vec<u8>> {
+        let mut buf = vec![];
+        if let Some(level) = compression {
             let mut encoder = ZlibEncoder::new(vec![], Compression::new(level as _));
             self.write_index(&mut encoder)?;
 
             let original_size = encoder.total_in();
-            let buf = encoder.finish()?;
-            stream.write_u64_le(buf.len() as _).await?;
-            stream.write_u64_le(original_size).await?;
-            buf
+            let data = encoder.finish()?;
+            buf.write_u8(1)?;
+            buf.write_u64::<LittleEndian>(data.len() as _)?;
+            buf.write_u64::<LittleEndian>(original_size)?;
+            buf.extend_from_slice(&data);
         } else {
-            stream.write_u8(0).await?;
-
-            let mut buf = vec![];
+            buf.write_u8(0)?;
+            buf.write_u64::<LittleEndian>(0)?;
             self.write_index(&mut buf)?;
 
-            stream.write_u64_le(buf.len() as _).await?;
-            buf
-        };
+            let size = buf.len() as u64 - 9;
+            buf[1..9].copy_from_slice(&size.to_le_bytes());
+        }
 
-        stream.write_all(&buf).await?;
-        Ok(())
+        Ok(buf)
     }
 
     fn write_index(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.check_hashed_names()?;
+
         let mut buf = vec![];
         let mut string_buf = vec![];
-        for (entry, &segment_start) in self.entries.iter().zip(self.file_starts.iter()) {
-            write_file(
-                entry,
-                &self.segments,
-                Some(segment_start),
-                &mut string_buf,
-                &mut buf,
-            )?;
-
-            write_segment(XP3_INDEX_FILE_IDENTIFIER, buf.len() as _, writer)?;
-            writer.write_all(&buf)?;
-            buf.clear();
-            string_buf.clear();
+        let mut chunks = self.chunks.iter();
+        let mut names = self
+            .entries
+            .iter()
+            .filter(|entry| entry.hashed_name.is_some());
+        let mut files = self.entries.iter().zip(self.file_starts.iter());
+
+        // Write chunks in their original order, rest are written in default order
+        for kind in &self.layout {
+            match kind {
+                IndexChunk::Unknown => {
+                    if let Some(chunk) = chunks.next() {
+                        write_chunk(chunk, writer)?;
+                    }
+                }
+
+                IndexChunk::Name => {
+                    if let Some(entry) = names.next() {
+                        self.write_name_chunk(entry, &mut string_buf, writer)?;
+                    }
+                }
+
+                IndexChunk::File => {
+                    if let Some((entry, &segment_start)) = files.next() {
+                        self.write_file_chunk(
+                            entry,
+                            segment_start,
+                            &mut string_buf,
+                            &mut buf,
+                            writer,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        for chunk in chunks {
+            write_chunk(chunk, writer)?;
+        }
+
+        // Write real names of hashed entries
+        for entry in names {
+            self.write_name_chunk(entry, &mut string_buf, writer)?;
         }
 
+        for (entry, &segment_start) in files {
+            self.write_file_chunk(entry, segment_start, &mut string_buf, &mut buf, writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_name_chunk(
+        &self,
+        entry: &XP3FileEntry,
+        string_buf: &mut Vec<u16>,
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        string_buf.extend(entry.name.encode_utf16());
+        write_segment(self.name_tag, 6 + string_buf.len() as u64 * 2, writer)?;
+        writer.write_u32::<LittleEndian>(entry.checksum)?;
+        write_name(string_buf, writer)?;
+        string_buf.clear();
         Ok(())
     }
+
+    fn write_file_chunk(
+        &self,
+        entry: &XP3FileEntry,
+        segment_start: usize,
+        string_buf: &mut Vec<u16>,
+        buf: &mut Vec<u8>,
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        write_file(entry, &self.segments, Some(segment_start), string_buf, buf)?;
+
+        write_segment(XP3_INDEX_FILE_IDENTIFIER, buf.len() as _, writer)?;
+        writer.write_all(buf)?;
+        buf.clear();
+        string_buf.clear();
+        Ok(())
+    }
+
+    /// Check real names can be resolved back to hashed entries.
+    /// Names are matched by checksum, so entries sharing checksum with a hashed entry must be hashed too.
+    fn check_hashed_names(&self) -> io::Result<()> {
+        let hashed = self
+            .entries
+            .iter()
+            .filter(|entry| entry.hashed_name.is_some())
+            .map(|entry| entry.checksum)
+            .collect::<HashSet<_>>();
+        if hashed.is_empty() {
+            return Ok(());
+        }
+
+        match self
+            .entries
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| entry.hashed_name.is_none() && hashed.contains(&entry.checksum))
+        {
+            Some((index, entry)) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "entry #{index} {:?} shares checksum {:#X} with a hashed entry, real names would be ambiguous",
+                    entry.name, entry.checksum
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
 }
 
 fn write_file(
@@ -70,29 +164,117 @@ fn write_file(
     string_buf: &mut Vec<u16>,
     writer: &mut impl Write,
 ) -> io::Result<()> {
-    string_buf.extend(entry.name.encode_utf16());
+    // Known chunks are written once at their first position in original order
+    let mut written = vec![];
+    let mut unknown_chunks = entry.unknown_chunks.iter();
+    for &tag in &entry.chunk_order {
+        if !KNOWN_FILE_CHUNKS.contains(&tag) {
+            if let Some(chunk) = unknown_chunks.next() {
+                write_chunk(chunk, writer)?;
+            }
+        } else if !written.contains(&tag) {
+            written.push(tag);
+            write_file_chunk(tag, entry, segments, segment_start, string_buf, writer)?;
+        }
+    }
+
+    for tag in [
+        XP3_INDEX_INFO_IDENTIFIER,
+        XP3_INDEX_TIME_IDENTIFIER,
+        XP3_INDEX_ADLR_IDENTIFIER,
+    ] {
+        if !written.contains(&tag) {
+            write_file_chunk(tag, entry, segments, segment_start, string_buf, writer)?;
+        }
+    }
+
+    for chunk in unknown_chunks {
+        write_chunk(chunk, writer)?;
+    }
+
+    if !written.contains(&XP3_INDEX_SEGM_IDENTIFIER) {
+        write_file_chunk(
+            XP3_INDEX_SEGM_IDENTIFIER,
+            entry,
+            segments,
+            segment_start,
+            string_buf,
+            writer,
+        )?;
+    }
+
+    Ok(())
+}
+
+const KNOWN_FILE_CHUNKS: [u32; 4] = [
+    XP3_INDEX_INFO_IDENTIFIER,
+    XP3_INDEX_TIME_IDENTIFIER,
+    XP3_INDEX_ADLR_IDENTIFIER,
+    XP3_INDEX_SEGM_IDENTIFIER,
+];
+
+/// Write known chunk `tag` of file index
+fn write_file_chunk(
+    tag: u32,
+    entry: &XP3FileEntry,
+    segments: &[DataSegment],
+    segment_start: Option<usize>,
+    string_buf: &mut Vec<u16>,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    match tag {
+        XP3_INDEX_INFO_IDENTIFIER => write_info(entry, string_buf, writer),
+
+        XP3_INDEX_TIME_IDENTIFIER => match entry.timestamp {
+            Some(timestamp) => {
+                write_segment(XP3_INDEX_TIME_IDENTIFIER, 8, writer)?;
+                writer.write_u64::<LittleEndian>(timestamp)
+            }
+            None => Ok(()),
+        },
+
+        XP3_INDEX_ADLR_IDENTIFIER => {
+            write_segment(XP3_INDEX_ADLR_IDENTIFIER, 4, writer)?;
+            writer.write_u32::<LittleEndian>(entry.checksum)
+        }
+
+        _ => write_segments(segments, segment_start, writer),
+    }
+}
+
+fn write_info(
+    entry: &XP3FileEntry,
+    string_buf: &mut Vec<u16>,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    string_buf.extend(
+        entry
+            .hashed_name
+            .as_ref()
+            .unwrap_or(&entry.name)
+            .encode_utf16(),
+    );
     write_segment(
         XP3_INDEX_INFO_IDENTIFIER,
         22 + string_buf.len() as u64 * 2,
         writer,
     )?;
-    writer.write_u32::<LittleEndian>(entry.protected as u32)?;
+    writer.write_u32::<LittleEndian>(if entry.protected {
+        XP3_PROTECTED_FLAG
+    } else {
+        0
+    })?;
     writer.write_u64::<LittleEndian>(entry.size)?;
     writer.write_u64::<LittleEndian>(entry.archive_size)?;
 
-    writer.write_u16::<LittleEndian>(string_buf.len() as u16)?;
-    for &mut ch in &mut *string_buf {
-        writer.write_u16::<LittleEndian>(ch)?;
-    }
-
-    if let Some(timestamp) = entry.timestamp {
-        write_segment(XP3_INDEX_TIME_IDENTIFIER, 8, writer)?;
-        writer.write_u64::<LittleEndian>(timestamp)?;
-    }
-
-    write_segment(XP3_INDEX_ADLR_IDENTIFIER, 4, writer)?;
-    writer.write_u32::<LittleEndian>(entry.checksum)?;
+    write_name(string_buf, writer)
+}
 
+fn write_segments(
+    segments: &[DataSegment],
+    segment_start: Option<usize>,
+    writer: &mut impl Write,
+) -> io::Result<()> {
     let mut next_index = segment_start;
     while let Some(seg_index) = next_index {
         let segment = segments[seg_index];
@@ -113,3 +295,17 @@ fn write_segment(id: u32, size: u64, writer: &mut impl Write) -> io::Result<()>
     writer.write_u64::<LittleEndian>(size)?;
     Ok(())
 }
+
+fn write_chunk(chunk: &XP3Chunk, writer: &mut impl Write) -> io::Result<()> {
+    write_segment(chunk.tag, chunk.data.len() as _, writer)?;
+    writer.write_all(&chunk.data)
+}
+
+fn write_name(name: &[u16], writer: &mut impl Write) -> io::Result<()> {
+    writer.write_u16::<LittleEndian>(name.len() as u16)?;
+    for &ch in name {
+        writer.write_u16::<LittleEndian>(ch)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use core::fmt::{self, Display, Formatter};
+use std::io;
+
+use crate::limits::XP3LimitKind;
+
+#[derive(Debug, thiserror::Error)]
+pub enum XP3OpenError {
+    #[error("Invalid xp3 header")]
+    InvalidHeader,
+    /// Malformed index chunk with the tag
+    #[deprecated(note = "malformed chunks are reported as `TruncatedChunk` or `MissingChunk`")]
+    #[error("Invalid xp3 section: {0:#X}")]
+    InvalidSection(u32),
+    /// Index data ends before its stated size
+    #[error("Truncated index, expected: {expected} bytes actual: {actual} bytes")]
+    TruncatedIndex { expected: u64, actual: u64 },
+    /// Chunk does not fit in the data containing it
+    #[error("Truncated chunk {location}, expected: {expected} bytes actual: {actual} bytes")]
+    TruncatedChunk {
+        location: IndexLocation,
+        expected: u64,
+        actual: u64,
+    },
+    /// Chunk required by the format is missing
+    #[error("Missing chunk {location}")]
+    MissingChunk { location: IndexLocation },
+    /// Index exceeds a limit set on open
+    #[error("{kind} limit exceeded, limit: {limit} actual: {actual}")]
+    LimitExceeded {
+        kind: XP3LimitKind,
+        limit: u64,
+        actual: u64,
+    },
+    /// Index is inconsistent with itself or the archive, returned by strict open
+    #[error("Inconsistent index, {} problems found", .0.len())]
+    Inconsistent(Vec<XP3Problem>),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Location of a malformed chunk in archive index
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexLocation {
+    /// Offset of the chunk in decompressed index data
+    pub offset: u64,
+    /// Tags of the chunk and its parents from the top level, e.g. `File/segm`
+    pub path: String,
+    /// Index of the file entry containing the chunk
+    pub entry: Option<usize>,
+    /// Name of the file entry if its `info` chunk is read already
+    pub name: Option<String>,
+}
+
+impl Display for IndexLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at index offset {:#X}", self.path, self.offset)?;
+        match (self.entry, &self.name) {
+            (Some(entry), Some(name)) => write!(f, " (entry #{entry} {name:?})"),
+            (Some(entry), None) => write!(f, " (entry #{entry})"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Inconsistency of archive index found by validation
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum XP3Problem {
+    /// `File` chunk has no `info` chunk, the entry has empty name
+    #[error("Entry #{entry} has no info chunk")]
+    MissingInfo { entry: usize },
+    /// Length of `segm` chunk is not a multiple of 28 bytes, the remainder is ignored
+    #[error("Entry #{entry} {name:?} has segm chunk of {length} bytes, not a multiple of 28")]
+    SegmentChunkLength {
+        entry: usize,
+        name: String,
+        length: u64,
+    },
+    /// `File` chunk has no `adlr` chunk, the checksum of the entry is not verified
+    #[error("Entry #{entry} {name:?} has no adlr chunk")]
+    MissingChecksum { entry: usize, name: String },
+    /// File size in `info` chunk differs from the sum of segment sizes
+    #[error("Entry #{entry} {name:?} size mismatch, info: {info} bytes segments: {segments} bytes")]
+    SizeMismatch {
+        entry: usize,
+        name: String,
+        info: u64,
+        segments: u64,
+    },
+    /// Archive size in `info` chunk differs from the sum of segment archive sizes
+    #[error(
+        "Entry #{entry} {name:?} archive size mismatch, info: {info} bytes segments: {segments} bytes"
+    )]
+    ArchiveSizeMismatch {
+        entry: usize,
+        name: String,
+        info: u64,
+        segments: u64,
+    },
+    /// Segment data ends past the end of stream
+    #[error(
+        "Segment {segment} of entry #{entry} {name:?} ends at {end:#X}, past the end of stream at {len:#X}"
+    )]
+    SegmentPastEnd {
+        entry: usize,
+        name: String,
+        /// Index of the segment in the file
+        segment: usize,
+        /// Stream offset of the segment end
+        end: u64,
+        /// Stream length
+        len: u64,
+    },
+    /// Segment shares part of its data with another segment.
+    /// Segments with identical data range are not reported since deduplicated files share them.
+    #[error(
+        "Segment {segment} of entry #{entry} {name:?} overlaps segment {other_segment} of entry #{other_entry} {other_name:?}"
+    )]
+    OverlappingSegments {
+        entry: usize,
+        name: String,
+        segment: usize,
+        other_entry: usize,
+        other_name: String,
+        other_segment: usize,
+    },
+}
+
+/// Error reading file data of an entry.
+/// Reads of [`XP3File`](crate::sync::read::XP3File) return it inside of [`io::Error`], see [`XP3ReadError::try_from`].
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to read entry #{index} {name:?} at offset {offset:#X}: {kind}")]
+pub struct XP3ReadError {
+    /// Index of the entry
+    pub index: usize,
+    /// Name of the entry
+    pub name: String,
+    /// Offset in file data where the error occurred
+    pub offset: u64,
+    pub kind: XP3ReadErrorKind,
+}
+
+impl XP3ReadError {
+    #[inline]
+    pub(crate) fn new(index: usize, name: &str, offset: u64, kind: XP3ReadErrorKind) -> Self {
+        Self {
+            index,
+            name: name.to_string(),
+            offset,
+            kind,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum XP3ReadErrorKind {
+    #[error(transparent)]
+    ChecksumMismatch(#[from] ChecksumMismatch),
+    /// Data of a segment does not match the size stored in index
+    #[error("Segment {segment} size mismatch, expected: {expected} bytes actual: {actual} bytes")]
+    SegmentSize {
+        /// Index of the segment in the file
+        segment: usize,
+        expected: u64,
+        actual: u64,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<XP3ReadError> for io::Error {
+    fn from(err: XP3ReadError) -> Self {
+        let kind = match err.kind {
+            XP3ReadErrorKind::Io(ref err) => err.kind(),
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl TryFrom<io::Error> for XP3ReadError {
+    type Error = io::Error;
+
+    /// Take [`XP3ReadError`] out of error returned by file reads
+    fn try_from(err: io::Error) -> Result<Self, io::Error> {
+        if !err
+            .get_ref()
+            .is_some_and(|inner| inner.is::<XP3ReadError>())
+        {
+            return Err(err);
+        }
+
+        Ok(*err.into_inner().unwrap().downcast().unwrap())
+    }
+}
+
+/// Adler32 checksum of file data read does not match the one stored in index.
+/// Returned as [`XP3ReadErrorKind::ChecksumMismatch`] at the end of file.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Checksum mismatch, expected: {expected:#X} actual: {actual:#X}")]
+pub struct ChecksumMismatch {
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl From<ChecksumMismatch> for io::Error {
+    fn from(err: ChecksumMismatch) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Error of file streams used after a previous error left them in an unknown state
+pub(crate) fn poisoned() -> io::Error {
+    io::Error::other("file stream is unusable due to a previous error")
+}
@@ -0,0 +1,142 @@
+//! Reading state of files shared by readers of every api
+
+use std::io::{self, SeekFrom};
+
+use adler32::RollingAdler32;
+
+use crate::{
+    archive::ArchiveState,
+    entry::{DataSegment, XP3FileEntry, seek_offset},
+    error::{ChecksumMismatch, XP3ReadError, XP3ReadErrorKind},
+    filter::XP3Filter,
+};
+
+/// Position, checksum and segments of a file being read, independent of the stream it is read from
+pub(crate) struct FileCursor<'a> {
+    pub index: usize,
+    /// Offset of the archive in its stream
+    pub start: u64,
+    pub segments: &'a [DataSegment],
+    pub start_segment: usize,
+    pub entry: &'a XP3FileEntry,
+    filter: Option<&'a dyn XP3Filter>,
+    checksum: Option<RollingAdler32>,
+    size: u64,
+    pub pos: u64,
+}
+
+impl<'a> FileCursor<'a> {
+    pub fn new(state: &'a ArchiveState, index: usize, verify: bool) -> Self {
+        let entries = &state.entries;
+        let segments = &entries.segments[..];
+        let start_segment = entries.file_starts[index];
+        Self {
+            index,
+            start: state.start,
+            segments,
+            start_segment,
+            entry: &entries.entries[index],
+            filter: state.filter.as_deref(),
+            checksum: state.verifies(index, verify).then(RollingAdler32::new),
+            size: DataSegment::file_size(segments, start_segment),
+            pos: 0,
+        }
+    }
+
+    #[inline]
+    /// First segment of the file
+    pub fn first_segment(&self) -> DataSegment {
+        self.segments[self.start_segment]
+    }
+
+    /// Create error at current position
+    pub fn error(&self, kind: impl Into<XP3ReadErrorKind>) -> XP3ReadError {
+        XP3ReadError::new(self.index, &self.entry.name, self.pos, kind.into())
+    }
+
+    /// Add position to error of underlying stream
+    pub fn wrap_error(&self, err: io::Error) -> XP3ReadError {
+        XP3ReadError::try_from(err).unwrap_or_else(|err| self.error(err))
+    }
+
+    /// Check size of segment at its end
+    pub fn check_segment(&self, segment: usize, segment_offset: u64) -> Result<(), XP3ReadError> {
+        self.check_size(segment, self.pos.saturating_sub(segment_offset), true)
+    }
+
+    /// Check `read` bytes read from current position do not run past the end of segment
+    pub fn check_overrun(
+        &self,
+        segment: usize,
+        segment_offset: u64,
+        read: usize,
+    ) -> Result<(), XP3ReadError> {
+        let actual = (self.pos + read as u64).saturating_sub(segment_offset);
+        self.check_size(segment, actual, false)
+    }
+
+    fn check_size(&self, segment: usize, actual: u64, exact: bool) -> Result<(), XP3ReadError> {
+        let expected = self.segments[segment].size;
+        if actual > expected || exact && actual != expected {
+            return Err(self.error(XP3ReadErrorKind::SegmentSize {
+                segment: segment - self.start_segment,
+                expected,
+                actual,
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Check checksum of data read after reaching end of file
+    pub fn check_checksum(&mut self) -> Result<(), XP3ReadError> {
+        if let Some(checksum) = self.checksum.take()
+            && checksum.hash() != self.entry.checksum
+        {
+            return Err(self.error(ChecksumMismatch {
+                expected: self.entry.checksum,
+                actual: checksum.hash(),
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Decrypt `data` read from current position, update checksum and advance position
+    pub fn consume(&mut self, data: &mut [u8]) {
+        if let Some(filter) = self.filter {
+            filter.decrypt(self.entry, self.pos, data);
+        }
+        if let Some(ref mut checksum) = self.checksum {
+            checksum.update_buffer(data);
+        }
+        self.pos += data.len() as u64;
+    }
+
+    /// Compute file offset of a seek.
+    /// Checksum is not verified anymore if the offset differs from current position.
+    pub fn seek_target(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = seek_offset(self.pos, self.size, pos)?;
+        if offset != self.pos {
+            // Data is not read sequentially anymore
+            self.checksum = None;
+        }
+
+        Ok(offset)
+    }
+
+    /// Whether `offset` is ahead of current position inside of compressed `segment`,
+    /// so it can be reached by skipping decompressed data
+    pub fn skips_to(&self, segment: usize, segment_offset: u64, offset: u64) -> bool {
+        let data_segment = self.segments[segment];
+        data_segment.compressed
+            && offset > self.pos
+            && offset < segment_offset.saturating_add(data_segment.size)
+    }
+
+    /// Find segment containing `offset`.
+    /// Returns segment index and file offset where the segment begins.
+    pub fn find(&self, offset: u64) -> Option<(usize, u64)> {
+        DataSegment::find(self.segments, self.start_segment, offset)
+    }
+}
@@ -0,0 +1,27 @@
+//! Filter transforming file data, used to implement encryption of protected archives
+
+use core::fmt::Debug;
+
+use crate::entry::XP3FileEntry;
+
+/// Filter applied to file data.
+/// Data is decrypted after decompression on read and encrypted before compression on write.
+pub trait XP3Filter: Debug + Send + Sync {
+    /// Decrypt data of `entry` in place.
+    /// `offset` is the position of `buf` inside of the file.
+    fn decrypt(&self, entry: &XP3FileEntry, offset: u64, buf: &mut [u8]);
+
+    /// Encrypt data of `entry` in place.
+    /// `offset` is the position of `buf` inside of the file.
+    ///
+    /// Calls [`XP3Filter::decrypt`] by default since most of schemes are symmetric.
+    fn encrypt(&self, entry: &XP3FileEntry, offset: u64, buf: &mut [u8]) {
+        self.decrypt(entry, offset, buf);
+    }
+
+    /// Whether data is keyed by [`XP3FileEntry::checksum`].
+    /// Writers buffer data of such files until the checksum is known, unless it is set in advance.
+    fn needs_checksum(&self) -> bool {
+        false
+    }
+}
@@ -1,6 +1,151 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::{XP3_CURRENT_VER_IDENTIFIER, XP3_MAGIC, XP3_VERSION_IDENTIFIER, error::XP3OpenError};
+
 #[derive(Debug, Clone, Copy)]
 /// XP3 Archive version
 pub enum XP3Version {
     Old,
     Current { minor: u32 },
 }
+
+impl XP3Version {
+    /// Encode archive header.
+    /// Returns header and position of the index offset placeholder inside of it.
+    pub(crate) fn encode_header(self) -> (Vec<u8>, u64) {
+        let mut buf = Vec::with_capacity(40);
+        buf.extend_from_slice(&XP3_MAGIC);
+        buf.push(1);
+
+        match self {
+            XP3Version::Old => {}
+            XP3Version::Current { minor } => {
+                buf.extend_from_slice(&XP3_CURRENT_VER_IDENTIFIER.to_le_bytes());
+                buf.extend_from_slice(&minor.to_le_bytes());
+                buf.push(XP3_VERSION_IDENTIFIER);
+                buf.extend_from_slice(&0_u64.to_le_bytes());
+            }
+        }
+
+        let pos = buf.len() as u64;
+        // placeholder index offset value
+        buf.extend_from_slice(&0_u64.to_le_bytes());
+        (buf, pos)
+    }
+}
+
+/// Archive header read from stream
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct XP3Header {
+    pub version: XP3Version,
+    /// Position of index offset relative to the archive start
+    pub index_offset_pos: u64,
+    /// Index offset relative to the archive start
+    pub index_start: u64,
+}
+
+impl XP3Header {
+    /// Read header of archive starting at current position
+    pub fn read(stream: &mut (impl Read + Seek)) -> Result<Self, XP3OpenError> {
+        let start = stream.stream_position()?;
+
+        let mut signature = [0; XP3_MAGIC.len()];
+        stream.read_exact(&mut signature)?;
+        if signature != XP3_MAGIC {
+            return Err(XP3OpenError::InvalidHeader);
+        }
+        let _ = stream.read_u8()?;
+
+        let version = match stream.read_u64::<LittleEndian>()? {
+            XP3_CURRENT_VER_IDENTIFIER => {
+                let minor = stream.read_u32::<LittleEndian>()?;
+                if stream.read_u8()? != XP3_VERSION_IDENTIFIER {
+                    return Err(XP3OpenError::InvalidHeader);
+                }
+
+                let index_offset = stream.read_u64::<LittleEndian>()?;
+                stream.seek(SeekFrom::Current(index_offset as _))?;
+                XP3Version::Current { minor }
+            }
+
+            _ => {
+                stream.seek(SeekFrom::Current(-8))?;
+                XP3Version::Old
+            }
+        };
+
+        let index_offset_pos = stream.stream_position()?;
+        Self::new(
+            version,
+            start,
+            index_offset_pos,
+            stream.read_u64::<LittleEndian>()?,
+        )
+    }
+
+    #[cfg(feature = "tokio")]
+    /// Read header of archive starting at current position
+    pub async fn read_async(
+        stream: &mut (impl tokio::io::AsyncRead + tokio::io::AsyncSeek + Unpin),
+    ) -> Result<Self, XP3OpenError> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let start = stream.stream_position().await?;
+
+        let mut signature = [0; XP3_MAGIC.len()];
+        stream.read_exact(&mut signature).await?;
+        if signature != XP3_MAGIC {
+            return Err(XP3OpenError::InvalidHeader);
+        }
+        let _ = stream.read_u8().await?;
+
+        let version = match stream.read_u64_le().await? {
+            XP3_CURRENT_VER_IDENTIFIER => {
+                let minor = stream.read_u32_le().await?;
+                if stream.read_u8().await? != XP3_VERSION_IDENTIFIER {
+                    return Err(XP3OpenError::InvalidHeader);
+                }
+
+                let index_offset = stream.read_u64_le().await?;
+                stream.seek(SeekFrom::Current(index_offset as _)).await?;
+                XP3Version::Current { minor }
+            }
+
+            _ => {
+                stream.seek(SeekFrom::Current(-8)).await?;
+                XP3Version::Old
+            }
+        };
+
+        let index_offset_pos = stream.stream_position().await?;
+        Self::new(
+            version,
+            start,
+            index_offset_pos,
+            stream.read_u64_le().await?,
+        )
+    }
+
+    /// Create header from stream positions, checking the index is reachable from `start`
+    fn new(
+        version: XP3Version,
+        start: u64,
+        index_offset_pos: u64,
+        index_start: u64,
+    ) -> Result<Self, XP3OpenError> {
+        let Some(index_offset_pos) = index_offset_pos.checked_sub(start) else {
+            return Err(XP3OpenError::InvalidHeader);
+        };
+        if start.checked_add(index_start).is_none() {
+            return Err(XP3OpenError::InvalidHeader);
+        }
+
+        Ok(Self {
+            version,
+            index_offset_pos,
+            index_start,
+        })
+    }
+}
@@ -1,11 +1,35 @@
 //! A XP3(krkr) archive library for rust.
+//!
+//! Blocking api over [`std::io`] is available in [`sync`] module.
+#![cfg_attr(
+    feature = "tokio",
+    doc = "Tokio api is available in [`read`] and [`mod@write`] module with `tokio` feature (enabled by default)."
+)]
+#![cfg_attr(
+    not(feature = "tokio"),
+    doc = "Tokio api is available in `read` and `write` module with `tokio` feature (enabled by default)."
+)]
 //! ## Examples
 //! See `examples` directory for various code examples.
 
+mod archive;
+pub mod crypt;
 mod entry;
+pub mod error;
+mod file;
+pub mod filter;
 pub mod header;
+pub mod limits;
+pub mod name;
+pub mod options;
+#[cfg(feature = "tokio")]
 pub mod read;
+pub mod sync;
+#[cfg(feature = "tokio")]
 pub mod write;
+mod writer;
+
+pub use entry::{XP3Chunk, XP3FileEntry};
 
 pub const XP3_MAGIC: [u8; 10] = [0x58, 0x50, 0x33, 0x0D, 0x0A, 0x20, 0x0A, 0x1A, 0x8B, 0x67];
 
@@ -14,6 +38,8 @@ pub const XP3_CURRENT_VER_IDENTIFIER: u64 = 0x17;
 pub const XP3_VERSION_IDENTIFIER: u8 = 128;
 
 pub const XP3_INDEX_FILE_IDENTIFIER: u32 = 1701603654; // File
+pub const XP3_INDEX_HNFN_IDENTIFIER: u32 = 1852206696; // hnfn
+pub const XP3_INDEX_ELIF_IDENTIFIER: u32 = 1181314149; // eliF
 
 pub const XP3_INDEX_INFO_IDENTIFIER: u32 = 1868983913; // info
 pub const XP3_INDEX_SEGM_IDENTIFIER: u32 = 1835492723; // segm
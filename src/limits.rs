@@ -0,0 +1,69 @@
+//! Resource limits applied while reading untrusted archives
+
+use core::fmt::{self, Display, Formatter};
+
+/// Limits of archive index checked on open.
+/// Opening fails with [`XP3OpenError::LimitExceeded`](crate::error::XP3OpenError::LimitExceeded) if any is exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XP3Limits {
+    /// Maximum size of index data, both stored and decompressed
+    pub max_index_size: u64,
+    /// Maximum number of entries
+    pub max_entries: usize,
+    /// Maximum length of entry names in UTF-16 code units
+    pub max_name_len: usize,
+    /// Maximum ratio of decompressed to stored size of compressed index
+    pub max_compression_ratio: u64,
+    /// Maximum ratio of decompressed to stored size of compressed file segments
+    pub max_segment_compression_ratio: u64,
+}
+
+impl XP3Limits {
+    /// Default limits, large enough for any real archive.
+    /// Names are limited to the longest Windows path and the ratio allows any valid deflate stream.
+    pub const DEFAULT: Self = Self {
+        max_index_size: 256 * 1024 * 1024,
+        max_entries: 1 << 20,
+        max_name_len: 32767,
+        max_compression_ratio: 1032,
+        max_segment_compression_ratio: 1032,
+    };
+
+    /// No limits other than the format itself
+    pub const UNLIMITED: Self = Self {
+        max_index_size: u64::MAX,
+        max_entries: usize::MAX,
+        max_name_len: usize::MAX,
+        max_compression_ratio: u64::MAX,
+        max_segment_compression_ratio: u64::MAX,
+    };
+}
+
+impl Default for XP3Limits {
+    #[inline]
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Limit exceeded by archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XP3LimitKind {
+    IndexSize,
+    Entries,
+    NameLength,
+    CompressionRatio,
+    SegmentCompressionRatio,
+}
+
+impl Display for XP3LimitKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            XP3LimitKind::IndexSize => "Index size",
+            XP3LimitKind::Entries => "Entry count",
+            XP3LimitKind::NameLength => "Name length",
+            XP3LimitKind::CompressionRatio => "Index compression ratio",
+            XP3LimitKind::SegmentCompressionRatio => "Segment compression ratio",
+        })
+    }
+}
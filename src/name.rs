@@ -0,0 +1,162 @@
+//! Entry name lookup
+
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::entry::XP3FileEntry;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// How entry names are matched on lookup
+pub enum NameMatching {
+    /// Names must be identical
+    #[default]
+    Exact,
+    /// Kirikiri style matching.
+    /// Names are compared case-insensitively and `\` is treated same as `/`.
+    /// Like Kirikiri, only ASCII letters are folded, so names like `Ä.txt` and `ä.txt` differ.
+    Normalized,
+}
+
+impl NameMatching {
+    /// Normalize name for lookup, lowercasing with [`str::to_ascii_lowercase`] if normalized
+    pub fn normalize(self, name: &str) -> String {
+        match self {
+            NameMatching::Exact => name.to_string(),
+            NameMatching::Normalized => name.replace('\\', "/").to_ascii_lowercase(),
+        }
+    }
+}
+
+/// Maps entry names to its index
+#[derive(Debug, Default)]
+pub(crate) struct NameIndex {
+    matching: NameMatching,
+    map: HashMap<String, usize>,
+}
+
+impl NameIndex {
+    pub fn new(matching: NameMatching, entries: &[XP3FileEntry]) -> Self {
+        let mut map = HashMap::with_capacity(entries.len());
+        for (index, entry) in entries.iter().enumerate() {
+            // Keep first entry if names are duplicated
+            map.entry(matching.normalize(&entry.name)).or_insert(index);
+        }
+
+        Self { matching, map }
+    }
+
+    #[inline]
+    pub const fn matching(&self) -> NameMatching {
+        self.matching
+    }
+
+    pub fn get(&self, name: &str) -> Option<usize> {
+        match self.matching {
+            NameMatching::Exact => self.map.get(name).copied(),
+            NameMatching::Normalized => self.map.get(&self.matching.normalize(name)).copied(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Change made to an unsafe entry name by [`sanitize_path`]
+pub enum NameFix {
+    /// Leading separators of absolute path removed
+    Root,
+    /// Drive letter prefix removed
+    Drive,
+    /// `..` component removed
+    ParentDir,
+    /// NUL character replaced with `_`
+    Nul,
+    /// Control or Windows-invalid character replaced with `_`
+    InvalidChar(char),
+    /// Windows-reserved device name component prefixed with `_`
+    Reserved(String),
+    /// Trailing dots and spaces of component, stripped by Windows, replaced with `_`
+    TrailingDot,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Relative path made from an entry name
+pub struct SanitizedPath {
+    /// Relative path, empty if nothing is left of the name
+    pub path: PathBuf,
+    /// Changes made to the name, empty if it was safe
+    pub fixes: Vec<NameFix>,
+}
+
+/// Windows-reserved device names
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Make a relative path safe to join onto a directory from an entry name.
+/// `\` is treated as separator and `.` components are dropped.
+/// Rules are applied same on every platform so extracted paths do not differ.
+pub fn sanitize_path(name: &str) -> SanitizedPath {
+    let mut fixes = vec![];
+    let mut fix = |fix: NameFix| {
+        if !fixes.contains(&fix) {
+            fixes.push(fix);
+        }
+    };
+
+    let mut name = name.replace('\\', "/");
+    if name.starts_with('/') {
+        fix(NameFix::Root);
+        name = name.trim_start_matches('/').to_string();
+    }
+    if let [drive, b':', ..] = name.as_bytes()
+        && drive.is_ascii_alphabetic()
+    {
+        fix(NameFix::Drive);
+        name = name[2..].trim_start_matches('/').to_string();
+    }
+
+    let mut path = PathBuf::new();
+    for part in name.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => {
+                fix(NameFix::ParentDir);
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut part = part
+            .chars()
+            .map(|c| match c {
+                '\0' => {
+                    fix(NameFix::Nul);
+                    '_'
+                }
+                '\x01'..='\x1f' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => {
+                    fix(NameFix::InvalidChar(c));
+                    '_'
+                }
+                c => c,
+            })
+            .collect::<String>();
+
+        let trimmed = part.trim_end_matches(['.', ' ']).len();
+        if trimmed < part.len() {
+            fix(NameFix::TrailingDot);
+            part.replace_range(trimmed.., &"_".repeat(part.len() - trimmed));
+        }
+
+        let stem = part.split('.').next().unwrap_or_default().trim_end();
+        if RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+        {
+            fix(NameFix::Reserved(part.clone()));
+            part.insert(0, '_');
+        }
+
+        path.push(part);
+    }
+
+    SanitizedPath { path, fixes }
+}
@@ -0,0 +1,121 @@
+//! Options for opening archives
+
+use std::{
+    io::{self, BufRead, Read, Seek, Write},
+    sync::Arc,
+};
+
+use crate::{
+    error::XP3OpenError,
+    filter::XP3Filter,
+    limits::XP3Limits,
+    name::NameMatching,
+    sync::{
+        XP3Editor, XP3Writer,
+        read::{ReadAt, XP3Recovery, XP3SharedArchive},
+    },
+};
+
+#[derive(Debug, Clone, Default)]
+/// Options for opening archives, like [`std::fs::OpenOptions`].
+/// Setters can be chained, then `open` methods open archives of each api with the options.
+pub struct XP3ArchiveOptions {
+    pub(crate) name_matching: NameMatching,
+    pub(crate) filter: Option<Arc<dyn XP3Filter>>,
+    pub(crate) verify_checksum: bool,
+    pub(crate) strict: bool,
+    pub(crate) limits: XP3Limits,
+}
+
+impl XP3ArchiveOptions {
+    /// Options used by `open` of archives
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set name matching used for lookup
+    pub fn name_matching(&mut self, matching: NameMatching) -> &mut Self {
+        self.name_matching = matching;
+        self
+    }
+
+    /// Set filter applied to file data
+    pub fn filter(&mut self, filter: Option<Arc<dyn XP3Filter>>) -> &mut Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Set whether files verify checksum of data read
+    pub fn verify_checksum(&mut self, verify: bool) -> &mut Self {
+        self.verify_checksum = verify;
+        self
+    }
+
+    /// Set whether opening fails with [`XP3OpenError::Inconsistent`] if index validation finds any problem
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Set limits of index, opening fails with [`XP3OpenError::LimitExceeded`] if any is exceeded
+    pub fn limits(&mut self, limits: XP3Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Open and index XP3 archive with blocking api
+    pub fn open<T: BufRead + Seek>(
+        &self,
+        stream: T,
+    ) -> Result<crate::sync::XP3Archive<T>, XP3OpenError> {
+        crate::sync::XP3Archive::open_with(stream, self)
+    }
+
+    /// Open XP3 archive with blocking api, salvaging what is readable if it is damaged.
+    /// Strict mode is not applied. See [`XP3Archive::open_recover`](crate::sync::XP3Archive::open_recover)
+    pub fn open_recover<T: BufRead + Seek>(
+        &self,
+        stream: T,
+    ) -> io::Result<(crate::sync::XP3Archive<T>, XP3Recovery)> {
+        crate::sync::XP3Archive::recover_with(stream, self)
+    }
+
+    /// Open and index XP3 archive over a [`ReadAt`] source
+    pub fn open_shared<R: ReadAt>(&self, source: R) -> Result<XP3SharedArchive<R>, XP3OpenError> {
+        XP3SharedArchive::open_with(source, self)
+    }
+
+    /// Open existing archive to add files with blocking api.
+    /// Limits apply to the existing index and filter encrypts new files. See [`XP3Writer::append`]
+    pub fn append<T: Read + Write + Seek>(&self, stream: T) -> Result<XP3Writer<T>, XP3OpenError> {
+        XP3Writer::append_with(stream, self)
+    }
+
+    /// Open and index XP3 archive for editing.
+    /// Limits apply to the existing index and filter encrypts replaced files. See [`XP3Editor::open`]
+    pub fn edit<T: Read + Seek>(&self, stream: T) -> Result<XP3Editor<T>, XP3OpenError> {
+        XP3Editor::open_with(stream, self)
+    }
+
+    #[cfg(feature = "tokio")]
+    /// Open and index XP3 archive with tokio api
+    pub async fn open_async<T>(&self, stream: T) -> Result<crate::read::XP3Archive<T>, XP3OpenError>
+    where
+        T: tokio::io::AsyncBufRead + tokio::io::AsyncSeek + Unpin,
+    {
+        crate::read::XP3Archive::open_with(stream, self).await
+    }
+
+    #[cfg(feature = "tokio")]
+    /// Open existing archive to add files with tokio api. See [`XP3ArchiveOptions::append`]
+    pub async fn append_async<T>(
+        &self,
+        stream: T,
+    ) -> Result<crate::write::XP3Writer<T>, XP3OpenError>
+    where
+        T: tokio::io::AsyncRead + tokio::io::AsyncWrite + tokio::io::AsyncSeek + Unpin,
+    {
+        crate::write::XP3Writer::append_with(stream, self).await
+    }
+}
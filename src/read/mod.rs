@@ -1,27 +1,30 @@
-pub mod error;
 mod stream;
 
+pub use crate::error;
+
 use async_compression::tokio::bufread::ZlibDecoder;
 use core::{
     mem,
     pin::Pin,
-    task::{Context, Poll},
+    task::{Context, Poll, ready},
 };
 use std::io::SeekFrom;
 use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf};
 
 use crate::{
-    XP3_CURRENT_VER_IDENTIFIER, XP3_MAGIC, XP3_VERSION_IDENTIFIER,
-    entry::{DataSegment, XP3Entries, XP3FileEntry},
-    header::XP3Version,
-    read::{error::XP3OpenError, stream::XP3Stream},
+    archive::{ArchiveState, state_accessors},
+    entry::XP3Entries,
+    error::{XP3OpenError, XP3Problem, XP3ReadError, poisoned},
+    file::FileCursor,
+    header::{XP3Header, XP3Version},
+    options::XP3ArchiveOptions,
+    read::stream::XP3Stream,
 };
 
 #[derive(Debug)]
 pub struct XP3Archive<T> {
     pub version: XP3Version,
-    entries: XP3Entries,
-    start: u64,
+    state: ArchiveState,
     stream: T,
 }
 
@@ -30,54 +33,94 @@ where
     T: AsyncBufRead + AsyncSeek + Unpin,
 {
     /// Open and index XP3 archive
-    pub async fn open(mut stream: T) -> Result<Self, XP3OpenError> {
+    pub async fn open(stream: T) -> Result<Self, XP3OpenError> {
+        Self::open_with(stream, &XP3ArchiveOptions::new()).await
+    }
+
+    pub(crate) async fn open_with(
+        mut stream: T,
+        options: &XP3ArchiveOptions,
+    ) -> Result<Self, XP3OpenError> {
         let start = stream.stream_position().await?;
 
-        let mut signature = [0; XP3_MAGIC.len()];
-        stream.read_exact(&mut signature).await?;
-        if signature != XP3_MAGIC {
-            return Err(XP3OpenError::InvalidHeader);
+        let header = XP3Header::read_async(&mut stream).await?;
+        stream
+            .seek(SeekFrom::Start(start + header.index_start))
+            .await?;
+        let entries = XP3Entries::open_async(&mut stream, &options.limits).await?;
+
+        let mut archive = Self {
+            version: header.version,
+            state: ArchiveState::new(entries, start, options),
+            stream,
+        };
+        if options.strict {
+            let problems = archive.validate().await?;
+            if !problems.is_empty() {
+                return Err(XP3OpenError::Inconsistent(problems));
+            }
         }
-        let _ = stream.read_u8().await?;
 
-        let (version, index_start) = match stream.read_u64_le().await? {
-            XP3_CURRENT_VER_IDENTIFIER => {
-                let minor = stream.read_u32_le().await?;
-                if stream.read_u8().await? != XP3_VERSION_IDENTIFIER {
-                    return Err(XP3OpenError::InvalidHeader);
-                }
+        Ok(archive)
+    }
 
-                let index_offset = stream.read_u64_le().await?;
-                stream.seek(SeekFrom::Current(index_offset as _)).await?;
+    /// Check consistency of index with itself and the stream length.
+    /// Returns every problem found
+    pub async fn validate(&mut self) -> io::Result<Vec<XP3Problem>> {
+        let len = self.stream.seek(SeekFrom::End(0)).await?;
+        Ok(self.state.entries.validate(self.state.start, len))
+    }
 
-                let index_start = stream.read_u64_le().await?;
-                (XP3Version::Current { minor }, index_start)
-            }
+    state_accessors!();
 
-            index_start => (XP3Version::Old, index_start),
-        };
+    /// Open an [`XP3File`] by index
+    pub async fn by_index<'a>(
+        &'a mut self,
+        index: usize,
+    ) -> Option<Result<XP3File<'a, T>, XP3ReadError>> {
+        self.open_file(index, self.state.verify_checksum).await
+    }
 
-        stream.seek(SeekFrom::Start(start + index_start)).await?;
-        let entries = XP3Entries::open(&mut stream).await?;
+    async fn open_file(
+        &mut self,
+        index: usize,
+        verify: bool,
+    ) -> Option<Result<XP3File<'_, T>, XP3ReadError>> {
+        self.state.entries.file_starts.get(index)?;
+        Some(XP3File::open(&self.state, index, verify, &mut self.stream).await)
+    }
 
-        Ok(Self {
-            version,
-            entries,
-            start,
-            stream,
-        })
+    /// Open an [`XP3File`] by name
+    pub async fn by_name<'a>(
+        &'a mut self,
+        name: &str,
+    ) -> Option<Result<XP3File<'a, T>, XP3ReadError>> {
+        let index = self.index_of(name)?;
+        self.by_index(index).await
     }
 
-    #[inline]
-    /// List entries
-    pub fn entries(&self) -> &[XP3FileEntry] {
-        &self.entries.entries
+    /// Read every file and verify its checksum.
+    /// Returns errors of corrupted files, checksum mismatches are reported as [`XP3ReadErrorKind::ChecksumMismatch`](crate::error::XP3ReadErrorKind::ChecksumMismatch).
+    pub async fn verify_all(&mut self) -> Vec<XP3ReadError> {
+        let mut corrupted = vec![];
+        for index in 0..self.state.entries.entries.len() {
+            let res = match self.open_file(index, true).await.unwrap() {
+                Ok(mut file) => io::copy(&mut file, &mut io::sink())
+                    .await
+                    .map_err(|err| file.cursor.wrap_error(err)),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = res {
+                corrupted.push(err);
+            }
+        }
+
+        corrupted
     }
 
-    /// Open an [`XP3File`] by index
-    pub async fn by_index<'a>(&'a mut self, index: usize) -> Option<io::Result<XP3File<'a, T>>> {
-        let start = self.entries.segments[*self.entries.file_starts.get(index)?];
-        Some(XP3File::open(self.start, &self.entries.segments, start, &mut self.stream).await)
+    /// Entries, archive start and stream to copy raw file data from
+    pub(crate) fn raw_parts(&mut self) -> (&XP3Entries, u64, &mut T) {
+        (&self.state.entries, self.state.start, &mut self.stream)
     }
 
     #[inline]
@@ -86,9 +129,10 @@ where
     }
 }
 
+/// Reader of a file in archive.
+/// Errors of reads contain [`XP3ReadError`], which can be taken out with [`XP3ReadError::try_from`].
 pub struct XP3File<'a, T> {
-    start: u64,
-    segments: &'a [DataSegment],
+    cursor: FileCursor<'a>,
     state: State<'a, T>,
 }
 
@@ -97,23 +141,157 @@ where
     T: AsyncBufRead + AsyncSeek + Unpin,
 {
     async fn open(
-        start: u64,
-        segments: &'a [DataSegment],
-        start_seg: DataSegment,
+        state: &'a ArchiveState,
+        index: usize,
+        verify: bool,
         stream: &'a mut T,
-    ) -> io::Result<Self> {
+    ) -> Result<Self, XP3ReadError> {
+        let cursor = FileCursor::new(state, index, verify);
+        let segment = cursor.first_segment();
+        let pos = segment
+            .position(cursor.start, 0)
+            .map_err(|err| cursor.error(err))?;
         stream
-            .seek(SeekFrom::Start(start + start_seg.start))
-            .await?;
+            .seek(SeekFrom::Start(pos))
+            .await
+            .map_err(|err| cursor.error(err))?;
         Ok(XP3File {
-            start,
-            segments,
             state: State::Read {
-                stream: create_file_stream(start_seg.compressed, start_seg.archive_size, stream),
-                next: start_seg.next,
+                stream: create_file_stream(
+                    segment.compressed,
+                    segment.archive_size,
+                    segment.size,
+                    stream,
+                ),
+                segment: cursor.start_segment,
+                segment_offset: 0,
             },
+            cursor,
         })
     }
+
+    #[inline]
+    /// Index of the entry
+    pub const fn index(&self) -> usize {
+        self.cursor.index
+    }
+
+    /// Start seeking to segment at `index` and `offset` of it.
+    /// Stored segments are seeked directly, compressed segments are decompressed and skipped.
+    fn start_segment(
+        &mut self,
+        mut stream: &'a mut T,
+        index: usize,
+        segment_offset: u64,
+        offset: u64,
+    ) -> io::Result<()> {
+        let segment = self.cursor.segments[index];
+        let (seek_offset, skip) = if segment.compressed {
+            (0, offset)
+        } else {
+            (offset, 0)
+        };
+
+        Pin::new(&mut stream).start_seek(SeekFrom::Start(
+            segment.position(self.cursor.start, seek_offset)?,
+        ))?;
+        self.state = State::Seek {
+            stream,
+            segment: index,
+            segment_offset,
+            skip,
+        };
+        Ok(())
+    }
+
+    /// Drive pending seek and skip of the file
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            return match mem::replace(&mut self.state, State::Poisoned) {
+                State::Seek {
+                    mut stream,
+                    segment,
+                    segment_offset,
+                    skip,
+                } => {
+                    if Pin::new(&mut stream).poll_complete(cx)?.is_pending() {
+                        self.state = State::Seek {
+                            stream,
+                            segment,
+                            segment_offset,
+                            skip,
+                        };
+                        return Poll::Pending;
+                    }
+
+                    let data_segment = self.cursor.segments[segment];
+                    let size = if data_segment.compressed {
+                        data_segment.archive_size
+                    } else {
+                        data_segment
+                            .archive_size
+                            .saturating_sub(self.cursor.pos.saturating_sub(segment_offset))
+                    };
+                    self.state = State::Skip {
+                        stream: create_file_stream(
+                            data_segment.compressed,
+                            size,
+                            data_segment.size,
+                            stream,
+                        ),
+                        segment,
+                        segment_offset,
+                        skip,
+                    };
+                    continue;
+                }
+
+                State::Skip {
+                    mut stream,
+                    segment,
+                    segment_offset,
+                    mut skip,
+                } => {
+                    let mut buf = [0; 4096];
+                    while skip > 0 {
+                        let mut read_buf = ReadBuf::new(&mut buf[..skip.min(4096) as usize]);
+                        if Pin::new(&mut stream)
+                            .poll_read(cx, &mut read_buf)?
+                            .is_pending()
+                        {
+                            self.state = State::Skip {
+                                stream,
+                                segment,
+                                segment_offset,
+                                skip,
+                            };
+                            return Poll::Pending;
+                        }
+
+                        let read = read_buf.filled().len() as u64;
+                        if read == 0 {
+                            return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                        }
+                        skip -= read;
+                    }
+
+                    self.state = State::Read {
+                        stream,
+                        segment,
+                        segment_offset,
+                    };
+                    Poll::Ready(Ok(()))
+                }
+
+                State::Poisoned => Poll::Ready(Err(poisoned())),
+
+                state => {
+                    self.state = state;
+                    Poll::Ready(Ok(()))
+                }
+            };
+        }
+    }
 }
 
 impl<'a, T> AsyncRead for XP3File<'a, T>
@@ -122,97 +300,204 @@ where
     T: AsyncBufRead + AsyncSeek + Unpin,
 {
     fn poll_read(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.poll_read_data(cx, buf)
+            .map_err(|err| this.cursor.wrap_error(err).into())
+    }
+}
+
+impl<'a, T> XP3File<'a, T>
+where
+    XP3Stream<&'a mut T>: AsyncRead,
+    T: AsyncBufRead + AsyncSeek + Unpin,
+{
+    fn poll_read_data(
+        &mut self,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         loop {
-            return match mem::replace(&mut self.state, State::Done) {
-                State::Read { mut stream, next } => {
+            ready!(self.poll_ready(cx))?;
+
+            return match mem::replace(&mut self.state, State::Poisoned) {
+                State::Read {
+                    mut stream,
+                    segment,
+                    segment_offset,
+                } => {
                     let filled = buf.filled().len();
                     let remaining = buf.remaining();
                     if Pin::new(&mut stream).poll_read(cx, buf)?.is_pending() {
-                        self.state = State::Read { stream, next };
+                        self.state = State::Read {
+                            stream,
+                            segment,
+                            segment_offset,
+                        };
                         return Poll::Pending;
                     }
 
-                    if remaining == 0 || filled != buf.filled().len() {
-                        self.state = State::Read { stream, next };
+                    let read = buf.filled().len() - filled;
+                    if let Err(err) = self.cursor.check_overrun(segment, segment_offset, read) {
+                        self.state = State::Done(stream.into_inner());
+                        return Poll::Ready(Err(err.into()));
+                    }
+
+                    if remaining == 0 || read != 0 {
+                        self.cursor.consume(&mut buf.filled_mut()[filled..]);
+                        self.state = State::Read {
+                            stream,
+                            segment,
+                            segment_offset,
+                        };
                         return Poll::Ready(Ok(()));
                     }
 
                     // Start next segment if exists
-                    let Some(next) = next else {
+                    let stream = stream.into_inner();
+                    let current = self.cursor.segments[segment];
+                    if let Err(err) = self.cursor.check_segment(segment, segment_offset) {
+                        self.state = State::Done(stream);
+                        return Poll::Ready(Err(err.into()));
+                    }
+                    let Some(next) = current.next else {
+                        self.state = State::Done(stream);
+                        self.cursor.check_checksum()?;
                         continue;
                     };
 
-                    let mut stream = stream.into_inner();
-                    let next_seg = self.segments[next];
-                    Pin::new(&mut stream)
-                        .start_seek(SeekFrom::Start(self.start + next_seg.start))?;
-                    self.state = State::Seek {
+                    self.start_segment(
                         stream,
-                        next: next_seg.next,
-                        compressed: next_seg.compressed,
-                        size: next_seg.archive_size,
-                    };
+                        next,
+                        segment_offset.saturating_add(current.size),
+                        0,
+                    )?;
                     continue;
                 }
 
-                State::Seek {
-                    mut stream,
-                    next,
-                    compressed,
-                    size,
-                } => {
-                    let poll = Pin::new(&mut stream).poll_complete(cx)?;
-                    if poll.is_ready() {
-                        self.state = State::Read {
-                            stream: create_file_stream(compressed, size, stream),
-                            next,
-                        };
-                        continue;
-                    } else {
-                        self.state = State::Seek {
-                            stream,
-                            next,
-                            compressed,
-                            size,
-                        };
-
-                        Poll::Pending
-                    }
+                state @ State::Done(_) => {
+                    self.state = state;
+                    Poll::Ready(Ok(()))
                 }
 
-                State::Done => Poll::Ready(Ok(())),
+                State::Poisoned => Poll::Ready(Err(poisoned())),
+
+                State::Seek { .. } | State::Skip { .. } => unreachable!(),
             };
         }
     }
 }
 
+impl<'a, T> AsyncSeek for XP3File<'a, T>
+where
+    XP3Stream<&'a mut T>: AsyncRead,
+    T: AsyncBufRead + AsyncSeek + Unpin,
+{
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        this.start_seek_data(position)
+            .map_err(|err| this.cursor.wrap_error(err).into())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        ready!(this.poll_ready(cx)).map_err(|err| this.cursor.wrap_error(err))?;
+        Poll::Ready(Ok(this.cursor.pos))
+    }
+}
+
+impl<'a, T> XP3File<'a, T>
+where
+    XP3Stream<&'a mut T>: AsyncRead,
+    T: AsyncBufRead + AsyncSeek + Unpin,
+{
+    fn start_seek_data(&mut self, position: SeekFrom) -> io::Result<()> {
+        let offset = self.cursor.seek_target(position)?;
+        if offset == self.cursor.pos {
+            return Ok(());
+        }
+
+        let stream = match mem::replace(&mut self.state, State::Poisoned) {
+            // Skip forward inside of current compressed segment
+            State::Read {
+                stream,
+                segment,
+                segment_offset,
+            } if self.cursor.skips_to(segment, segment_offset, offset) => {
+                self.state = State::Skip {
+                    stream,
+                    segment,
+                    segment_offset,
+                    skip: offset - self.cursor.pos,
+                };
+                self.cursor.pos = offset;
+                return Ok(());
+            }
+
+            State::Read { stream, .. } => stream.into_inner(),
+            State::Done(stream) => stream,
+            state @ (State::Seek { .. } | State::Skip { .. }) => {
+                self.state = state;
+                return Err(io::Error::other(
+                    "other file operation is pending, call poll_complete before start_seek",
+                ));
+            }
+            State::Poisoned => return Err(poisoned()),
+        };
+
+        match self.cursor.find(offset) {
+            Some((index, segment_offset)) => {
+                self.cursor.pos = offset;
+                self.start_segment(stream, index, segment_offset, offset - segment_offset)?;
+            }
+
+            None => {
+                self.cursor.pos = offset;
+                self.state = State::Done(stream);
+            }
+        }
+
+        Ok(())
+    }
+}
+
 enum State<'a, T> {
     Read {
         stream: XP3Stream<&'a mut T>,
-        next: Option<usize>,
+        segment: usize,
+        segment_offset: u64,
     },
     Seek {
         stream: &'a mut T,
-        next: Option<usize>,
-        compressed: bool,
-        size: u64,
+        segment: usize,
+        segment_offset: u64,
+        skip: u64,
+    },
+    Skip {
+        stream: XP3Stream<&'a mut T>,
+        segment: usize,
+        segment_offset: u64,
+        skip: u64,
     },
-    Done,
+    Done(&'a mut T),
+    Poisoned,
 }
 
+/// Create stream reading `archive_size` bytes of segment data.
+/// Decompressed data is limited to a byte past segment `size` so overrun is detected without inflating the rest.
 fn create_file_stream<T: AsyncBufRead + Unpin + AsyncSeek>(
     compressed: bool,
+    archive_size: u64,
     size: u64,
     stream: T,
 ) -> XP3Stream<T> {
-    let stream = stream.take(size);
+    let stream = stream.take(archive_size);
 
     if compressed {
-        XP3Stream::Compressed(ZlibDecoder::new(stream))
+        XP3Stream::Compressed(ZlibDecoder::new(stream).take(size.saturating_add(1)))
     } else {
         XP3Stream::Raw(stream)
     }
@@ -31,14 +31,14 @@ where
 #[derive(Debug)]
 #[pin_project(project = XP3StreamProj)]
 pub enum XP3Stream<T> {
-    Compressed(#[pin] ZlibDecoder<Take<T>>),
+    Compressed(#[pin] Take<ZlibDecoder<Take<T>>>),
     Raw(#[pin] Take<T>),
 }
 
 impl<T: AsyncBufRead> XP3Stream<T> {
     pub fn into_inner(self) -> T {
         match self {
-            XP3Stream::Compressed(stream) => stream.into_inner().into_inner(),
+            XP3Stream::Compressed(stream) => stream.into_inner().into_inner().into_inner(),
             XP3Stream::Raw(stream) => stream.into_inner(),
         }
     }
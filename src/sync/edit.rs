@@ -0,0 +1,343 @@
+//! Removing, renaming and replacing files of existing archives
+
+use core::mem;
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    sync::Arc,
+};
+
+use adler32::RollingAdler32;
+
+use crate::{
+    entry::{DataSegment, XP3Chunk, XP3Entries, XP3FileEntry},
+    error::XP3OpenError,
+    filter::XP3Filter,
+    header::{XP3Header, XP3Version},
+    options::XP3ArchiveOptions,
+    sync::XP3Writer,
+};
+
+/// Pending change of a file
+#[derive(Debug)]
+enum Edit {
+    Keep,
+    Remove,
+    Replace {
+        data: Vec<u8>,
+        compression: Option<u8>,
+    },
+}
+
+/// Editor of an existing archive.
+/// Changes are recorded and applied on [`XP3Editor::commit`] in place,
+/// or on [`XP3Editor::write_to`] into a new compacted archive.
+#[derive(Debug)]
+pub struct XP3Editor<T> {
+    pub version: XP3Version,
+    start: u64,
+    index_offset_pos: u64,
+    entries: XP3Entries,
+    edits: Vec<Edit>,
+    filter: Option<Arc<dyn XP3Filter>>,
+    stream: T,
+}
+
+impl<T> XP3Editor<T>
+where
+    T: Read + Seek,
+{
+    /// Open and index XP3 archive for editing
+    pub fn open(stream: T) -> Result<Self, XP3OpenError> {
+        Self::open_with(stream, &XP3ArchiveOptions::new())
+    }
+
+    pub(crate) fn open_with(
+        mut stream: T,
+        options: &XP3ArchiveOptions,
+    ) -> Result<Self, XP3OpenError> {
+        let start = stream.stream_position()?;
+        let header = XP3Header::read(&mut stream)?;
+
+        stream.seek(SeekFrom::Start(start + header.index_start))?;
+        let entries = XP3Entries::open(&mut stream, &options.limits)?;
+
+        let edits = entries.entries.iter().map(|_| Edit::Keep).collect();
+        Ok(Self {
+            version: header.version,
+            start,
+            index_offset_pos: header.index_offset_pos,
+            entries,
+            edits,
+            filter: options.filter.clone(),
+            stream,
+        })
+    }
+
+    #[inline]
+    /// List entries, including removed ones until changes are applied
+    pub fn entries(&self) -> &[XP3FileEntry] {
+        &self.entries.entries
+    }
+
+    #[inline]
+    /// Unknown top level index chunks
+    pub fn unknown_chunks(&self) -> &[XP3Chunk] {
+        &self.entries.chunks
+    }
+
+    /// Find index of an entry not removed by name
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries
+            .entries
+            .iter()
+            .zip(&self.edits)
+            .position(|(entry, edit)| entry.name == name && !matches!(edit, Edit::Remove))
+    }
+
+    #[inline]
+    /// Whether entry at `index` is removed
+    pub fn is_removed(&self, index: usize) -> bool {
+        matches!(self.edits.get(index), Some(Edit::Remove))
+    }
+
+    #[inline]
+    /// Filter encrypting replaced file data
+    pub fn filter(&self) -> Option<&Arc<dyn XP3Filter>> {
+        self.filter.as_ref()
+    }
+
+    /// Set filter encrypting replaced file data
+    pub fn set_filter(&mut self, filter: Option<Arc<dyn XP3Filter>>) {
+        self.filter = filter;
+    }
+
+    /// Remove file at `index`.
+    /// Returns `false` if index is out of range
+    pub fn remove(&mut self, index: usize) -> bool {
+        let Some(edit) = self.edits.get_mut(index) else {
+            return false;
+        };
+
+        *edit = Edit::Remove;
+        true
+    }
+
+    /// Rename file at `index`.
+    /// Returns `false` if index is out of range
+    pub fn rename(&mut self, index: usize, name: String) -> bool {
+        let Some(entry) = self.entries.entries.get_mut(index) else {
+            return false;
+        };
+
+        entry.name = name;
+        true
+    }
+
+    /// Replace data of file at `index`, other metadata is kept.
+    /// Returns `false` if index is out of range
+    pub fn replace(&mut self, index: usize, data: Vec<u8>, compression: Option<u8>) -> bool {
+        let Some(edit) = self.edits.get_mut(index) else {
+            return false;
+        };
+
+        *edit = Edit::Replace { data, compression };
+        true
+    }
+
+    /// Write archive with changes applied into `stream`.
+    /// Data of kept files is copied verbatim, leaving no unused space.
+    pub fn write_to<W: Write + Seek>(
+        &mut self,
+        stream: W,
+        compression: Option<u8>,
+    ) -> io::Result<W> {
+        let mut writer = XP3Writer::new(self.version, stream)?;
+        writer.set_filter(self.filter.clone());
+        writer.keep_layout(&self.entries);
+
+        // Maps data ranges of kept files to their new file index.
+        // Files sharing data have separate segments with identical ranges once the index is read.
+        let mut copied = HashMap::new();
+        for (index, edit) in self.edits.iter_mut().enumerate() {
+            let entry = self.entries.entries[index].clone();
+            match edit {
+                Edit::Keep => {
+                    let segments =
+                        DataSegment::chain(&self.entries.segments, self.entries.file_starts[index])
+                            .map(|(_, segment, _)| segment)
+                            .collect::<Vec<_>>();
+                    let ranges = segments
+                        .iter()
+                        .map(|segment| (segment.start, segment.archive_size))
+                        .collect::<Vec<_>>();
+                    if let Some(&id) = copied.get(&ranges) {
+                        writer.push_shared(entry, id);
+                    } else {
+                        let id = writer.push_raw(entry, segments, &mut self.stream, self.start)?;
+                        copied.insert(ranges, id);
+                    }
+                }
+
+                Edit::Remove => {}
+
+                Edit::Replace { data, compression } => {
+                    write_file(&mut writer, entry, data, *compression)?;
+                }
+            }
+        }
+
+        writer.finish(compression)
+    }
+}
+
+impl<T> XP3Editor<T>
+where
+    T: Read + Write + Seek,
+{
+    /// Move data of kept files toward the start of archive, filling space left by removed or replaced files.
+    /// Returns number of bytes reclaimed.
+    ///
+    /// Archive is left inconsistent until [`XP3Editor::commit`] succeeds.
+    pub fn compact(&mut self) -> io::Result<u64> {
+        let end = self.live_end();
+
+        let mut live = self.live_segments();
+        live.sort_unstable_by_key(|&index| self.entries.segments[index].start);
+
+        // Maps old start and size of moved segment data to its new start
+        let mut moved = HashMap::new();
+        let mut pos = self.index_offset_pos + 8;
+        for index in live {
+            let segment = self.entries.segments[index];
+            if let Some(&start) = moved.get(&(segment.start, segment.archive_size)) {
+                self.entries.segments[index].start = start;
+                continue;
+            }
+
+            if segment.start < pos {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "overlapping segments cannot be compacted",
+                ));
+            }
+
+            if segment.start != pos {
+                self.move_data(segment.start, pos, segment.archive_size)?;
+            }
+            moved.insert((segment.start, segment.archive_size), pos);
+            self.entries.segments[index].start = pos;
+            pos += segment.archive_size;
+        }
+
+        Ok(end.saturating_sub(pos))
+    }
+
+    /// Apply changes in place.
+    /// Data of removed and replaced files is left as unused space, see [`XP3Editor::compact`].
+    /// Replaced data and index are written after the end of kept data.
+    ///
+    /// Stale data may remain past the end of archive, truncate the stream at its position after commit if needed.
+    pub fn commit(mut self, compression: Option<u8>) -> io::Result<T> {
+        let end = self.live_end();
+        self.stream.seek(SeekFrom::Start(self.start + end))?;
+
+        let entries = mem::take(&mut self.entries);
+        let mut writer = XP3Writer::from_parts(
+            self.start,
+            self.start + self.index_offset_pos,
+            entries.layout(),
+            self.stream,
+        );
+        writer.set_filter(self.filter);
+
+        // Maps first segment of kept files to its new file index
+        let mut kept = HashMap::new();
+        for (index, (entry, edit)) in entries.entries.into_iter().zip(self.edits).enumerate() {
+            match edit {
+                Edit::Keep => {
+                    let start = entries.file_starts[index];
+                    if let Some(&id) = kept.get(&start) {
+                        writer.push_shared(entry, id);
+                    } else {
+                        let segments = DataSegment::chain(&entries.segments, start)
+                            .map(|(_, segment, _)| segment);
+                        kept.insert(start, writer.push_existing(entry, segments));
+                    }
+                }
+
+                Edit::Remove => {}
+
+                Edit::Replace { data, compression } => {
+                    write_file(&mut writer, entry, &data, compression)?;
+                }
+            }
+        }
+
+        writer.finish(compression)
+    }
+
+    /// Indices of segments of kept files
+    fn live_segments(&self) -> Vec<usize> {
+        let mut live = vec![false; self.entries.segments.len()];
+        for (index, edit) in self.edits.iter().enumerate() {
+            if let Edit::Keep = edit {
+                for (segment, _, _) in
+                    DataSegment::chain(&self.entries.segments, self.entries.file_starts[index])
+                {
+                    live[segment] = true;
+                }
+            }
+        }
+
+        (0..live.len()).filter(|&index| live[index]).collect()
+    }
+
+    /// End of kept data relative to the archive start
+    fn live_end(&self) -> u64 {
+        self.live_segments()
+            .into_iter()
+            .map(|index| {
+                let segment = self.entries.segments[index];
+                segment.start.saturating_add(segment.archive_size)
+            })
+            .fold(self.index_offset_pos + 8, u64::max)
+    }
+
+    /// Copy `size` bytes at `from` to lower offset `to`
+    fn move_data(&mut self, from: u64, to: u64, size: u64) -> io::Result<()> {
+        let mut buf = vec![0; 65536];
+        let mut moved = 0;
+        while moved < size {
+            let len = buf.len().min((size - moved) as usize);
+            self.stream
+                .seek(SeekFrom::Start(self.start + from + moved))?;
+            self.stream.read_exact(&mut buf[..len])?;
+            self.stream.seek(SeekFrom::Start(self.start + to + moved))?;
+            self.stream.write_all(&buf[..len])?;
+            moved += len as u64;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write replaced data of a file keeping its metadata
+fn write_file<W: Write + Seek>(
+    writer: &mut XP3Writer<W>,
+    entry: XP3FileEntry,
+    data: &[u8],
+    compression: Option<u8>,
+) -> io::Result<usize> {
+    let mut checksum = RollingAdler32::new();
+    checksum.update_buffer(data);
+
+    let mut file = writer.file(entry.name, entry.protected, compression)?;
+    file.timestamp(entry.timestamp);
+    file.hashed_name(entry.hashed_name);
+    file.unknown_chunks(entry.unknown_chunks);
+    file.chunk_order(entry.chunk_order);
+    file.checksum(Some(checksum.hash()));
+    file.write_all(data)?;
+    file.finish()
+}
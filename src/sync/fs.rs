@@ -0,0 +1,257 @@
+//! Packing directories into archives and extracting archives into directories
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Seek, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    name::{NameFix, NameMatching, SanitizedPath, sanitize_path},
+    sync::{XP3Archive, XP3Writer},
+};
+
+/// Seconds between 1601-01-01 and 1970-01-01
+const FILETIME_UNIX_OFFSET: u64 = 11644473600;
+
+/// Convert system time to `time` chunk timestamp, 100ns ticks since 1601-01-01 (Windows `FILETIME`)
+pub fn to_timestamp(time: SystemTime) -> u64 {
+    let ticks = |duration: Duration| {
+        duration
+            .as_secs()
+            .saturating_mul(10_000_000)
+            .saturating_add(duration.subsec_nanos() as u64 / 100)
+    };
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (FILETIME_UNIX_OFFSET * 10_000_000).saturating_add(ticks(duration)),
+        Err(err) => (FILETIME_UNIX_OFFSET * 10_000_000).saturating_sub(ticks(err.duration())),
+    }
+}
+
+/// Convert `time` chunk timestamp to system time
+pub fn from_timestamp(timestamp: u64) -> SystemTime {
+    let duration = Duration::new(
+        timestamp / 10_000_000,
+        (timestamp % 10_000_000) as u32 * 100,
+    );
+    let offset = Duration::from_secs(FILETIME_UNIX_OFFSET);
+    match duration.checked_sub(offset) {
+        Some(duration) => UNIX_EPOCH + duration,
+        None => UNIX_EPOCH - (offset - duration),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Options of [`pack_dir`]
+pub struct PackOptions {
+    /// Compression level of files
+    pub compression: Option<u8>,
+    /// Protected flag of files
+    pub protected: bool,
+    /// Write modification time of files in `time` chunk
+    pub timestamp: bool,
+    /// Glob patterns of names to pack, every file is packed if empty
+    pub include: Vec<String>,
+    /// Glob patterns of names to skip
+    pub exclude: Vec<String>,
+}
+
+/// Pack every file under directory `path` recursively.
+/// Entries are named by their path relative to `path` joined with `/`, in sorted order.
+/// Symbolic links are followed, except links to a directory containing them.
+/// Returns file indices
+pub fn pack_dir<T: Write + Seek>(
+    writer: &mut XP3Writer<T>,
+    path: impl AsRef<Path>,
+    options: &PackOptions,
+) -> io::Result<Vec<usize>> {
+    let mut files = vec![];
+    let path = path.as_ref();
+    collect_files(
+        path,
+        String::new(),
+        &mut vec![fs::canonicalize(path)?],
+        &mut files,
+    )?;
+
+    let mut ids = vec![];
+    for (name, path) in files {
+        if !filter_name(&name, &options.include, &options.exclude) {
+            continue;
+        }
+
+        let file = File::open(&path)?;
+        let timestamp = if options.timestamp {
+            Some(to_timestamp(file.metadata()?.modified()?))
+        } else {
+            None
+        };
+
+        let mut writer = writer.file(name, options.protected, options.compression)?;
+        writer.timestamp(timestamp);
+        io::copy(&mut BufReader::new(file), &mut writer)?;
+        ids.push(writer.finish()?);
+    }
+
+    Ok(ids)
+}
+
+/// Collect files under `dir` recursively.
+/// `ancestors` are canonical paths of `dir` and its parents, skipped to avoid symbolic link loops
+fn collect_files(
+    dir: &Path,
+    prefix: String,
+    ancestors: &mut Vec<PathBuf>,
+    files: &mut Vec<(String, PathBuf)>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("file name is not valid unicode: {}", entry.path().display()),
+            ));
+        };
+        let name = prefix.clone() + file_name;
+
+        // Follow symbolic links
+        let metadata = fs::metadata(entry.path())?;
+        if metadata.is_dir() {
+            let canonical = fs::canonicalize(entry.path())?;
+            if ancestors.contains(&canonical) {
+                continue;
+            }
+
+            ancestors.push(canonical);
+            collect_files(&entry.path(), name + "/", ancestors, files)?;
+            ancestors.pop();
+        } else if metadata.is_file() {
+            files.push((name, entry.path()));
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+/// Options of [`extract_all`]
+pub struct ExtractOptions {
+    /// Set modification time of files from `time` chunk
+    pub timestamp: bool,
+    /// Glob patterns of names to extract, every file is extracted if empty
+    pub include: Vec<String>,
+    /// Glob patterns of names to skip
+    pub exclude: Vec<String>,
+    /// Extract entries with unsafe names under rewritten paths instead of rejecting them
+    pub rewrite_names: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Entries with unsafe names found by [`extract_all`]
+pub struct ExtractReport {
+    /// Index, path extracted to and changes made to the name of rewritten entries
+    pub rewritten: Vec<(usize, PathBuf, Vec<NameFix>)>,
+    /// Index and unsafe parts of the name of rejected entries
+    pub rejected: Vec<(usize, Vec<NameFix>)>,
+    /// Index of skipped entries and of the entry extracted earlier to the same path
+    pub duplicated: Vec<(usize, usize)>,
+}
+
+/// Extract every file of archive into directory `dest`.
+/// Names are made into relative paths by [`sanitize_path`], unsafe ones are rejected unless [`ExtractOptions::rewrite_names`] is set.
+/// Entries with nothing left of their names are always rejected.
+/// Entries with the same path as an entry extracted earlier, like duplicated or rewritten names, are skipped.
+/// Paths are compared ignoring ASCII case like [`NameMatching::Normalized`],
+/// so entries differing only in case do not overwrite each other on case-insensitive file systems.
+pub fn extract_all<T: BufRead + Seek>(
+    archive: &mut XP3Archive<T>,
+    dest: impl AsRef<Path>,
+    options: &ExtractOptions,
+) -> io::Result<ExtractReport> {
+    let dest = dest.as_ref();
+
+    let mut report = ExtractReport::default();
+    // Entry extracted to each normalized path
+    let mut extracted = HashMap::new();
+    for index in 0..archive.entries().len() {
+        let entry = &archive.entries()[index];
+        if !filter_name(
+            &entry.name.replace('\\', "/"),
+            &options.include,
+            &options.exclude,
+        ) {
+            continue;
+        }
+
+        let SanitizedPath { path, fixes } = sanitize_path(&entry.name);
+        if path.file_name().is_none() || !fixes.is_empty() && !options.rewrite_names {
+            report.rejected.push((index, fixes));
+            continue;
+        }
+        let timestamp = entry.timestamp.filter(|_| options.timestamp);
+
+        let normalized = NameMatching::Normalized.normalize(&path.to_string_lossy());
+        if let Some(&first) = extracted.get(&normalized) {
+            report.duplicated.push((index, first));
+            continue;
+        }
+        extracted.insert(normalized, index);
+        let path = dest.join(path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut out = BufWriter::new(File::create(&path)?);
+        io::copy(&mut archive.by_index(index).unwrap()?, &mut out)?;
+        let out = out.into_inner().map_err(io::IntoInnerError::into_error)?;
+        if let Some(timestamp) = timestamp {
+            out.set_modified(from_timestamp(timestamp))?;
+        }
+
+        if !fixes.is_empty() {
+            report.rewritten.push((index, path, fixes));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Check name against include and exclude patterns
+fn filter_name(name: &str, include: &[String], exclude: &[String]) -> bool {
+    (include.is_empty() || include.iter().any(|pattern| glob_match(pattern, name)))
+        && !exclude.iter().any(|pattern| glob_match(pattern, name))
+}
+
+/// Match name with glob pattern.
+/// `*` matches any characters except `/`, `**` matches any characters and `?` matches a character except `/`.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    fn match_from(pattern: &[char], name: &[char]) -> bool {
+        match pattern {
+            [] => name.is_empty(),
+            // `**/` also matches no directory
+            ['*', '*', '/', rest @ ..] => {
+                match_from(rest, name)
+                    || (0..name.len()).any(|i| name[i] == '/' && match_from(rest, &name[i + 1..]))
+            }
+            ['*', '*', rest @ ..] => (0..=name.len()).any(|i| match_from(rest, &name[i..])),
+            ['*', rest @ ..] => (0..=name.len())
+                .take_while(|&i| i == 0 || name[i - 1] != '/')
+                .any(|i| match_from(rest, &name[i..])),
+            ['?', rest @ ..] => {
+                matches!(name, [c, ..] if *c != '/') && match_from(rest, &name[1..])
+            }
+            [c, rest @ ..] => name.first() == Some(c) && match_from(rest, &name[1..]),
+        }
+    }
+
+    match_from(
+        &pattern.chars().collect::<Vec<_>>(),
+        &name.chars().collect::<Vec<_>>(),
+    )
+}
@@ -0,0 +1,10 @@
+//! Blocking api over [`std::io`]
+
+pub mod edit;
+pub mod fs;
+pub mod read;
+pub mod write;
+
+pub use edit::XP3Editor;
+pub use read::{XP3Archive, XP3File, XP3Recovery, XP3SharedArchive};
+pub use write::{XP3FileWriter, XP3PackFile, XP3Writer};
@@ -0,0 +1,390 @@
+mod recover;
+mod shared;
+mod stream;
+
+pub use recover::XP3Recovery;
+pub use shared::{ReadAt, SharedReader, XP3SharedArchive};
+
+use core::mem;
+use std::io::{self, BufRead, ErrorKind, Read, Seek, SeekFrom};
+
+use flate2::bufread::ZlibDecoder;
+
+use crate::{
+    archive::{ArchiveState, state_accessors},
+    entry::XP3Entries,
+    error::{XP3OpenError, XP3Problem, XP3ReadError, poisoned},
+    file::FileCursor,
+    header::{XP3Header, XP3Version},
+    limits::XP3Limits,
+    options::XP3ArchiveOptions,
+    sync::read::stream::XP3Stream,
+};
+
+#[derive(Debug)]
+pub struct XP3Archive<T> {
+    pub version: XP3Version,
+    state: ArchiveState,
+    stream: T,
+}
+
+impl<T> XP3Archive<T>
+where
+    T: BufRead + Seek,
+{
+    /// Open and index XP3 archive
+    pub fn open(stream: T) -> Result<Self, XP3OpenError> {
+        Self::open_with(stream, &XP3ArchiveOptions::new())
+    }
+
+    pub(crate) fn open_with(
+        mut stream: T,
+        options: &XP3ArchiveOptions,
+    ) -> Result<Self, XP3OpenError> {
+        let (version, start, entries) = read_archive(&mut stream, &options.limits)?;
+
+        let mut archive = Self {
+            version,
+            state: ArchiveState::new(entries, start, options),
+            stream,
+        };
+        if options.strict {
+            let problems = archive.validate()?;
+            if !problems.is_empty() {
+                return Err(XP3OpenError::Inconsistent(problems));
+            }
+        }
+
+        Ok(archive)
+    }
+
+    /// Check consistency of index with itself and the stream length.
+    /// Returns every problem found
+    pub fn validate(&mut self) -> io::Result<Vec<XP3Problem>> {
+        let len = self.stream.seek(SeekFrom::End(0))?;
+        Ok(self.state.entries.validate(self.state.start, len))
+    }
+
+    state_accessors!();
+
+    /// Open an [`XP3File`] by index
+    pub fn by_index(&mut self, index: usize) -> Option<Result<XP3File<'_, &mut T>, XP3ReadError>> {
+        self.open_file(index, self.state.verify_checksum)
+    }
+
+    fn open_file(
+        &mut self,
+        index: usize,
+        verify: bool,
+    ) -> Option<Result<XP3File<'_, &mut T>, XP3ReadError>> {
+        self.state.entries.file_starts.get(index)?;
+        Some(XP3File::open(&self.state, index, verify, &mut self.stream))
+    }
+
+    /// Open an [`XP3File`] by name
+    pub fn by_name(&mut self, name: &str) -> Option<Result<XP3File<'_, &mut T>, XP3ReadError>> {
+        let index = self.index_of(name)?;
+        self.by_index(index)
+    }
+
+    /// Read every file and verify its checksum.
+    /// Returns errors of corrupted files, checksum mismatches are reported as [`XP3ReadErrorKind::ChecksumMismatch`](crate::error::XP3ReadErrorKind::ChecksumMismatch).
+    pub fn verify_all(&mut self) -> Vec<XP3ReadError> {
+        let mut corrupted = vec![];
+        for index in 0..self.state.entries.entries.len() {
+            let res = self
+                .open_file(index, true)
+                .unwrap()
+                .and_then(|mut file| verify_file(&mut file));
+            if let Err(err) = res {
+                corrupted.push(err);
+            }
+        }
+
+        corrupted
+    }
+
+    /// Entries, archive start and stream to copy raw file data from
+    pub(crate) fn raw_parts(&mut self) -> (&XP3Entries, u64, &mut T) {
+        (&self.state.entries, self.state.start, &mut self.stream)
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+}
+
+/// Read archive header and index.
+/// Returns archive version, archive start and entries.
+fn read_archive(
+    stream: &mut (impl BufRead + Seek),
+    limits: &XP3Limits,
+) -> Result<(XP3Version, u64, XP3Entries), XP3OpenError> {
+    let start = stream.stream_position()?;
+    let header = XP3Header::read(stream)?;
+
+    stream.seek(SeekFrom::Start(start + header.index_start))?;
+    let entries = XP3Entries::open(stream, limits)?;
+    Ok((header.version, start, entries))
+}
+
+/// Read whole file to verify its data
+pub(crate) fn verify_file<T: BufRead + Seek>(
+    file: &mut XP3File<'_, T>,
+) -> Result<(), XP3ReadError> {
+    io::copy(file, &mut io::sink()).map_err(|err| file.cursor.wrap_error(err))?;
+    Ok(())
+}
+
+/// Reader of a file in archive.
+/// `T` is the stream the file is read from.
+/// Errors of reads contain [`XP3ReadError`], which can be taken out with [`XP3ReadError::try_from`].
+pub struct XP3File<'a, T> {
+    cursor: FileCursor<'a>,
+    state: State<T>,
+}
+
+impl<'a, T> XP3File<'a, T>
+where
+    T: BufRead + Seek,
+{
+    fn open(
+        state: &'a ArchiveState,
+        index: usize,
+        verify: bool,
+        mut stream: T,
+    ) -> Result<Self, XP3ReadError> {
+        let cursor = FileCursor::new(state, index, verify);
+        let segment = cursor.first_segment();
+        segment
+            .position(cursor.start, 0)
+            .and_then(|pos| stream.seek(SeekFrom::Start(pos)))
+            .map_err(|err| cursor.error(err))?;
+        Ok(XP3File {
+            state: State::Read {
+                stream: create_file_stream(
+                    segment.compressed,
+                    segment.archive_size,
+                    segment.size,
+                    stream,
+                ),
+                segment: cursor.start_segment,
+                segment_offset: 0,
+            },
+            cursor,
+        })
+    }
+
+    #[inline]
+    /// Index of the entry
+    pub const fn index(&self) -> usize {
+        self.cursor.index
+    }
+
+    /// Open segment at `index` with the stream positioned at `offset` of it.
+    /// Stored segments are seeked directly, compressed segments are decompressed and skipped.
+    fn open_segment(
+        &mut self,
+        mut stream: T,
+        index: usize,
+        segment_offset: u64,
+        offset: u64,
+    ) -> io::Result<()> {
+        let segment = self.cursor.segments[index];
+        let mut stream = if segment.compressed {
+            stream.seek(SeekFrom::Start(segment.position(self.cursor.start, 0)?))?;
+            create_file_stream(true, segment.archive_size, segment.size, stream)
+        } else {
+            stream.seek(SeekFrom::Start(
+                segment.position(self.cursor.start, offset)?,
+            ))?;
+            create_file_stream(
+                false,
+                segment.archive_size.saturating_sub(offset),
+                segment.size,
+                stream,
+            )
+        };
+
+        if segment.compressed {
+            skip(&mut stream, offset)?;
+        }
+
+        self.state = State::Read {
+            stream,
+            segment: index,
+            segment_offset,
+        };
+        Ok(())
+    }
+}
+
+impl<T> Read for XP3File<'_, T>
+where
+    T: BufRead + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_data(buf)
+            .map_err(|err| self.cursor.wrap_error(err).into())
+    }
+}
+
+impl<T> XP3File<'_, T>
+where
+    T: BufRead + Seek,
+{
+    fn read_data(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            return match mem::replace(&mut self.state, State::Poisoned) {
+                State::Read {
+                    mut stream,
+                    segment,
+                    segment_offset,
+                } => {
+                    let read = stream.read(buf)?;
+                    if let Err(err) = self.cursor.check_overrun(segment, segment_offset, read) {
+                        self.state = State::Done(stream.into_inner());
+                        return Err(err.into());
+                    }
+
+                    if buf.is_empty() || read != 0 {
+                        self.cursor.consume(&mut buf[..read]);
+                        self.state = State::Read {
+                            stream,
+                            segment,
+                            segment_offset,
+                        };
+                        return Ok(read);
+                    }
+
+                    // Start next segment if exists
+                    let stream = stream.into_inner();
+                    let current = self.cursor.segments[segment];
+                    if let Err(err) = self.cursor.check_segment(segment, segment_offset) {
+                        self.state = State::Done(stream);
+                        return Err(err.into());
+                    }
+                    let Some(next) = current.next else {
+                        self.state = State::Done(stream);
+                        self.cursor.check_checksum()?;
+                        continue;
+                    };
+
+                    self.open_segment(
+                        stream,
+                        next,
+                        segment_offset.saturating_add(current.size),
+                        0,
+                    )?;
+                    continue;
+                }
+
+                state @ State::Done(_) => {
+                    self.state = state;
+                    Ok(0)
+                }
+
+                State::Poisoned => Err(poisoned()),
+            };
+        }
+    }
+}
+
+impl<T> Seek for XP3File<'_, T>
+where
+    T: BufRead + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.seek_data(pos)
+            .map_err(|err| self.cursor.wrap_error(err).into())
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.cursor.pos)
+    }
+}
+
+impl<T> XP3File<'_, T>
+where
+    T: BufRead + Seek,
+{
+    fn seek_data(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = self.cursor.seek_target(pos)?;
+        if offset == self.cursor.pos {
+            return Ok(offset);
+        }
+
+        let stream = match mem::replace(&mut self.state, State::Poisoned) {
+            // Skip forward inside of current compressed segment
+            State::Read {
+                mut stream,
+                segment,
+                segment_offset,
+            } if self.cursor.skips_to(segment, segment_offset, offset) => {
+                skip(&mut stream, offset - self.cursor.pos)?;
+                self.state = State::Read {
+                    stream,
+                    segment,
+                    segment_offset,
+                };
+                self.cursor.pos = offset;
+                return Ok(offset);
+            }
+
+            State::Read { stream, .. } => stream.into_inner(),
+            State::Done(stream) => stream,
+            State::Poisoned => return Err(poisoned()),
+        };
+
+        match self.cursor.find(offset) {
+            Some((index, segment_offset)) => {
+                self.open_segment(stream, index, segment_offset, offset - segment_offset)?;
+            }
+
+            None => {
+                self.state = State::Done(stream);
+            }
+        }
+
+        self.cursor.pos = offset;
+        Ok(offset)
+    }
+}
+
+enum State<T> {
+    Read {
+        stream: XP3Stream<T>,
+        segment: usize,
+        segment_offset: u64,
+    },
+    Done(T),
+    Poisoned,
+}
+
+/// Create stream reading `archive_size` bytes of segment data.
+/// Decompressed data is limited to a byte past segment `size` so overrun is detected without inflating the rest.
+fn create_file_stream<T: BufRead>(
+    compressed: bool,
+    archive_size: u64,
+    size: u64,
+    stream: T,
+) -> XP3Stream<T> {
+    let stream = stream.take(archive_size);
+
+    if compressed {
+        XP3Stream::Compressed(ZlibDecoder::new(stream).take(size.saturating_add(1)))
+    } else {
+        XP3Stream::Raw(stream)
+    }
+}
+
+/// Read and discard `size` bytes
+fn skip(stream: &mut impl Read, size: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut stream.take(size), &mut io::sink())?;
+    if skipped < size {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+}
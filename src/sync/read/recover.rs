@@ -0,0 +1,240 @@
+use core::ops::Range;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+use adler32::RollingAdler32;
+use flate2::{Decompress, FlushDecompress, Status};
+
+use crate::{
+    archive::ArchiveState,
+    entry::{DataSegment, XP3Entries, XP3FileEntry},
+    error::XP3OpenError,
+    header::{XP3Header, XP3Version},
+    options::XP3ArchiveOptions,
+    sync::read::XP3Archive,
+};
+
+/// Size of blocks scanned for zlib headers at once
+const SCAN_BLOCK: u64 = 64 * 1024;
+
+/// Damage found by [`XP3Archive::open_recover`]
+#[derive(Debug, Default)]
+pub struct XP3Recovery {
+    /// Error which stopped reading header or index, none if archive is intact
+    pub error: Option<XP3OpenError>,
+    /// Number of entries read from index
+    pub salvaged: usize,
+    /// Number of entries carved from data, following salvaged entries
+    pub carved: usize,
+}
+
+impl<T> XP3Archive<T>
+where
+    T: BufRead + Seek,
+{
+    /// Open archive salvaging what is readable if it is damaged.
+    /// Entries are read from index up to the damage point, then compressed data not referenced by them
+    /// is scanned for zlib streams which are added as entries named by their offset, like `recovered/0000002A`.
+    /// Version is [`XP3Version::Current`] if header is unreadable.
+    pub fn open_recover(stream: T) -> io::Result<(Self, XP3Recovery)> {
+        Self::recover_with(stream, &XP3ArchiveOptions::new())
+    }
+
+    pub(crate) fn recover_with(
+        mut stream: T,
+        options: &XP3ArchiveOptions,
+    ) -> io::Result<(Self, XP3Recovery)> {
+        let start = stream.stream_position()?;
+        let len = stream.seek(SeekFrom::End(0))?.saturating_sub(start);
+        stream.seek(SeekFrom::Start(start))?;
+
+        let (version, region, mut entries, error) = match XP3Header::read(&mut stream) {
+            Ok(header) => {
+                stream.seek(SeekFrom::Start(start + header.index_start))?;
+                let (entries, error) = XP3Entries::open_lenient(&mut stream, &options.limits);
+
+                // File data lies between header and index
+                let data_start = header.index_offset_pos + 8;
+                let data_end = if (data_start..=len).contains(&header.index_start) {
+                    header.index_start
+                } else {
+                    len
+                };
+                (header.version, data_start..data_end, entries, error)
+            }
+
+            Err(err) => (
+                XP3Version::Current { minor: 1 },
+                0..len,
+                XP3Entries::default(),
+                Some(err),
+            ),
+        };
+
+        let salvaged = entries.entries.len();
+        if error.is_some() {
+            let mut covered = entries
+                .segments
+                .iter()
+                .map(|segment| segment.start..segment.start.saturating_add(segment.archive_size))
+                .collect::<Vec<_>>();
+            covered.sort_unstable_by_key(|range| range.start);
+
+            for blob in carve(&mut stream, start, region, &covered)? {
+                entries.push(
+                    XP3FileEntry {
+                        name: format!("recovered/{:08X}", blob.start),
+                        size: blob.size,
+                        archive_size: blob.archive_size,
+                        checksum: blob.checksum,
+                        ..Default::default()
+                    },
+                    [DataSegment {
+                        compressed: true,
+                        start: blob.start,
+                        size: blob.size,
+                        archive_size: blob.archive_size,
+                        next: None,
+                    }],
+                );
+            }
+        }
+        let carved = entries.entries.len() - salvaged;
+
+        Ok((
+            Self {
+                version,
+                state: ArchiveState::new(entries, start, options),
+                stream,
+            },
+            XP3Recovery {
+                error,
+                salvaged,
+                carved,
+            },
+        ))
+    }
+}
+
+/// Zlib stream found in archive data
+struct Blob {
+    /// Offset relative to the archive start
+    start: u64,
+    size: u64,
+    archive_size: u64,
+    checksum: u32,
+}
+
+/// Find zlib streams in `region` of archive at `start`, skipping `covered` ranges sorted by start
+fn carve(
+    stream: &mut (impl BufRead + Seek),
+    start: u64,
+    region: Range<u64>,
+    covered: &[Range<u64>],
+) -> io::Result<Vec<Blob>> {
+    let mut blobs = vec![];
+    let mut buf = vec![];
+    let mut pos = region.start;
+    while pos < region.end {
+        if let Some(range) = covered.iter().find(|range| range.contains(&pos)) {
+            pos = range.end;
+            continue;
+        }
+
+        // Streams cannot extend into the next covered range
+        let end = covered
+            .iter()
+            .map(|range| range.start)
+            .find(|&range_start| range_start > pos)
+            .map_or(region.end, |range_start| range_start.min(region.end));
+
+        stream.seek(SeekFrom::Start(start + pos))?;
+        buf.clear();
+        stream
+            .by_ref()
+            .take((end - pos).min(SCAN_BLOCK))
+            .read_to_end(&mut buf)?;
+
+        // Try every header candidate inside the block before reading the next one
+        let mut offset = 0;
+        pos = loop {
+            let Some(found) = buf[offset..]
+                .windows(2)
+                .position(|header| is_zlib_header(header[0], header[1]))
+            else {
+                // Keep the last byte as it may begin a header crossing the block boundary
+                break pos + (buf.len() as u64).saturating_sub(1).max(1);
+            };
+
+            let candidate = pos + (offset + found) as u64;
+            stream.seek(SeekFrom::Start(start + candidate))?;
+            match inflate(&mut stream.by_ref().take(end - candidate))? {
+                Some((size, archive_size, checksum)) if size > 0 => {
+                    blobs.push(Blob {
+                        start: candidate,
+                        size,
+                        archive_size,
+                        checksum,
+                    });
+
+                    let next = candidate + archive_size;
+                    if next - pos >= buf.len() as u64 {
+                        break next;
+                    }
+                    offset = (next - pos) as usize;
+                }
+
+                _ => offset += found + 1,
+            }
+        };
+    }
+
+    Ok(blobs)
+}
+
+/// Check zlib header using deflate without preset dictionary
+fn is_zlib_header(cmf: u8, flg: u8) -> bool {
+    cmf & 0x0F == 8
+        && cmf >> 4 <= 7
+        && flg & 0x20 == 0
+        && u16::from_be_bytes([cmf, flg]).is_multiple_of(31)
+}
+
+/// Decompress a zlib stream, discarding the output.
+/// Returns decompressed size, compressed size and adler32 checksum of the data if stream ends properly
+fn inflate(stream: &mut impl BufRead) -> io::Result<Option<(u64, u64, u32)>> {
+    let mut decompress = Decompress::new(true);
+    let mut checksum = RollingAdler32::new();
+    let mut out = vec![0; 32 * 1024];
+    loop {
+        let input = stream.fill_buf()?;
+        let flush = if input.is_empty() {
+            FlushDecompress::Finish
+        } else {
+            FlushDecompress::None
+        };
+
+        let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+        let Ok(status) = decompress.decompress(input, &mut out, flush) else {
+            return Ok(None);
+        };
+        let read = (decompress.total_in() - total_in) as usize;
+        let written = (decompress.total_out() - total_out) as usize;
+        stream.consume(read);
+        checksum.update_buffer(&out[..written]);
+
+        match status {
+            Status::StreamEnd => {
+                return Ok(Some((
+                    decompress.total_out(),
+                    decompress.total_in(),
+                    checksum.hash(),
+                )));
+            }
+
+            // Input ended before the stream
+            _ if read == 0 && written == 0 => return Ok(None),
+
+            _ => {}
+        }
+    }
+}
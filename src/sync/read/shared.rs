@@ -0,0 +1,449 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufRead, ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+    sync::Arc,
+};
+
+use adler32::RollingAdler32;
+use flate2::bufread::ZlibDecoder;
+
+use crate::{
+    archive::{ArchiveState, state_accessors},
+    entry::DataSegment,
+    error::{ChecksumMismatch, XP3OpenError, XP3Problem, XP3ReadError, XP3ReadErrorKind},
+    header::XP3Version,
+    options::XP3ArchiveOptions,
+    sync::read::{XP3File, read_archive, verify_file},
+};
+
+/// Source supporting reads at arbitrary offset without moving shared cursor.
+pub trait ReadAt {
+    /// Read bytes starting from `offset`.
+    /// Returns number of bytes read, 0 if `offset` is at the end.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Total length of source in bytes
+    fn len(&self) -> io::Result<u64>;
+
+    #[inline]
+    /// Whether source is empty
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl ReadAt for File {
+    #[cfg(unix)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let Some(data) = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.get(offset..))
+        else {
+            return Ok(0);
+        };
+
+        let size = buf.len().min(data.len());
+        buf[..size].copy_from_slice(&data[..size]);
+        Ok(size)
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        Ok(<[u8]>::len(self) as _)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        ReadAt::len(self.as_slice())
+    }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for &R {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        ReadAt::len(&**self)
+    }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for Box<R> {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        ReadAt::len(&**self)
+    }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for Arc<R> {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        ReadAt::len(&**self)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ReadAt for memmap2::Mmap {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        ReadAt::len(&**self)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl ReadAt for bytes::Bytes {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        ReadAt::len(&**self)
+    }
+}
+
+/// Buffered reader over a [`ReadAt`] source with its own cursor.
+#[derive(Debug)]
+pub struct SharedReader<'a, R: ?Sized> {
+    source: &'a R,
+    pos: u64,
+    buf: Box<[u8]>,
+    filled: usize,
+    consumed: usize,
+}
+
+impl<'a, R: ReadAt + ?Sized> SharedReader<'a, R> {
+    const BUF_SIZE: usize = 8192;
+
+    pub fn new(source: &'a R) -> Self {
+        Self {
+            source,
+            pos: 0,
+            buf: vec![0; Self::BUF_SIZE].into_boxed_slice(),
+            filled: 0,
+            consumed: 0,
+        }
+    }
+
+    #[inline]
+    fn discard_buffer(&mut self) {
+        self.filled = 0;
+        self.consumed = 0;
+    }
+}
+
+impl<R: ReadAt + ?Sized> Read for SharedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Bypass buffer for large reads
+        if self.consumed == self.filled && buf.len() >= Self::BUF_SIZE {
+            self.discard_buffer();
+            let read = self.source.read_at(buf, self.pos)?;
+            self.pos += read as u64;
+            return Ok(read);
+        }
+
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<R: ReadAt + ?Sized> BufRead for SharedReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed == self.filled {
+            self.filled = self.source.read_at(&mut self.buf, self.pos)?;
+            self.consumed = 0;
+        }
+
+        Ok(&self.buf[self.consumed..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        let amount = amount.min(self.filled - self.consumed);
+        self.consumed += amount;
+        self.pos += amount as u64;
+    }
+}
+
+impl<R: ReadAt + ?Sized> Seek for SharedReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "seeking from end is not supported",
+                ));
+            }
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        if pos != self.pos {
+            self.discard_buffer();
+            self.pos = pos;
+        }
+        Ok(pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
+
+/// XP3 archive over a [`ReadAt`] source.
+/// Multiple files can be read concurrently since every [`XP3File`] carries its own cursor.
+#[derive(Debug)]
+pub struct XP3SharedArchive<R> {
+    pub version: XP3Version,
+    state: ArchiveState,
+    source: R,
+}
+
+#[cfg(any(unix, windows))]
+impl XP3SharedArchive<File> {
+    /// Open and index XP3 archive file at `path`
+    pub fn open_path(path: impl AsRef<Path>) -> Result<Self, XP3OpenError> {
+        Self::open(File::open(path)?)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl XP3SharedArchive<memmap2::Mmap> {
+    /// Memory map and index XP3 archive file at `path`
+    ///
+    /// # Safety
+    /// The file must not be modified or truncated while the archive is alive.
+    /// See [`memmap2::Mmap::map`].
+    pub unsafe fn open_mmap(path: impl AsRef<Path>) -> Result<Self, XP3OpenError> {
+        let file = File::open(path)?;
+        Self::open(unsafe { memmap2::Mmap::map(&file)? })
+    }
+}
+
+impl<R: ReadAt> XP3SharedArchive<R> {
+    /// Open and index XP3 archive
+    pub fn open(source: R) -> Result<Self, XP3OpenError> {
+        Self::open_with(source, &XP3ArchiveOptions::new())
+    }
+
+    pub(crate) fn open_with(source: R, options: &XP3ArchiveOptions) -> Result<Self, XP3OpenError> {
+        let (version, start, entries) =
+            read_archive(&mut SharedReader::new(&source), &options.limits)?;
+
+        if options.strict {
+            let problems = entries.validate(start, source.len()?);
+            if !problems.is_empty() {
+                return Err(XP3OpenError::Inconsistent(problems));
+            }
+        }
+
+        Ok(Self {
+            version,
+            state: ArchiveState::new(entries, start, options),
+            source,
+        })
+    }
+
+    state_accessors!();
+
+    /// Open an [`XP3File`] by index
+    pub fn by_index(
+        &self,
+        index: usize,
+    ) -> Option<Result<XP3File<'_, SharedReader<'_, R>>, XP3ReadError>> {
+        self.open_file(index, self.state.verify_checksum)
+    }
+
+    fn open_file(
+        &self,
+        index: usize,
+        verify: bool,
+    ) -> Option<Result<XP3File<'_, SharedReader<'_, R>>, XP3ReadError>> {
+        self.state.entries.file_starts.get(index)?;
+        Some(XP3File::open(
+            &self.state,
+            index,
+            verify,
+            SharedReader::new(&self.source),
+        ))
+    }
+
+    /// Open an [`XP3File`] by name
+    pub fn by_name(
+        &self,
+        name: &str,
+    ) -> Option<Result<XP3File<'_, SharedReader<'_, R>>, XP3ReadError>> {
+        self.by_index(self.index_of(name)?)
+    }
+
+    /// Read every file and verify its checksum.
+    /// Returns errors of corrupted files, checksum mismatches are reported as [`XP3ReadErrorKind::ChecksumMismatch`](crate::error::XP3ReadErrorKind::ChecksumMismatch).
+    pub fn verify_all(&self) -> Vec<XP3ReadError> {
+        let mut corrupted = vec![];
+        for index in 0..self.state.entries.entries.len() {
+            let res = self
+                .open_file(index, true)
+                .unwrap()
+                .and_then(|mut file| verify_file(&mut file));
+            if let Err(err) = res {
+                corrupted.push(err);
+            }
+        }
+
+        corrupted
+    }
+
+    /// Check consistency of index with itself and the source length.
+    /// Returns every problem found
+    pub fn validate(&self) -> io::Result<Vec<XP3Problem>> {
+        Ok(self
+            .state
+            .entries
+            .validate(self.state.start, self.source.len()?))
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+
+impl<R: ReadAt + AsRef<[u8]>> XP3SharedArchive<R> {
+    /// Get content of a file by index from in-memory archive.
+    /// Stored files consisting of a single segment are borrowed without copying unless a filter is set,
+    /// others are decompressed and decrypted directly from the archive bytes.
+    /// Checksum is verified if [`XP3SharedArchive::verify_checksum`] is set, like reads of [`XP3File`].
+    pub fn bytes(&self, index: usize) -> Option<Result<Cow<'_, [u8]>, XP3ReadError>> {
+        let start = *self.state.entries.file_starts.get(index)?;
+        Some(self.read_bytes(index, start))
+    }
+
+    fn read_bytes(&self, index: usize, start: usize) -> Result<Cow<'_, [u8]>, XP3ReadError> {
+        let entry = &self.state.entries.entries[index];
+        let error = |offset, kind| XP3ReadError::new(index, &entry.name, offset, kind);
+
+        let mut data = Cow::Borrowed(&[][..]);
+        for (i, (_, segment, segment_offset)) in
+            DataSegment::chain(&self.state.entries.segments, start).enumerate()
+        {
+            let segment_data = self
+                .segment_data(segment)
+                .map_err(|err| error(segment_offset, err.into()))?;
+
+            let actual = if !segment.compressed && segment.next.is_none() && i == 0 {
+                data = Cow::Borrowed(segment_data);
+                segment_data.len() as u64
+            } else if segment.compressed {
+                // Reading a byte past stated size detects overrun of hostile data
+                let buf = data.to_mut();
+                let len = buf.len();
+                ZlibDecoder::new(segment_data)
+                    .take(segment.size.saturating_add(1))
+                    .read_to_end(buf)
+                    .map_err(|err| error(segment_offset, err.into()))?;
+                (buf.len() - len) as u64
+            } else {
+                data.to_mut().extend_from_slice(segment_data);
+                segment_data.len() as u64
+            };
+
+            if actual != segment.size {
+                return Err(error(
+                    segment_offset.saturating_add(actual.min(segment.size)),
+                    XP3ReadErrorKind::SegmentSize {
+                        segment: i,
+                        expected: segment.size,
+                        actual,
+                    },
+                ));
+            }
+        }
+
+        if let Some(ref filter) = self.state.filter {
+            filter.decrypt(entry, 0, data.to_mut());
+        }
+
+        if self.state.verifies(index, self.state.verify_checksum) {
+            let actual = RollingAdler32::from_buffer(&data).hash();
+            if actual != entry.checksum {
+                return Err(error(
+                    data.len() as u64,
+                    ChecksumMismatch {
+                        expected: entry.checksum,
+                        actual,
+                    }
+                    .into(),
+                ));
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Archive bytes of a segment
+    fn segment_data(&self, segment: DataSegment) -> io::Result<&[u8]> {
+        let start = self
+            .state
+            .start
+            .checked_add(segment.start)
+            .and_then(|start| usize::try_from(start).ok());
+        let size = usize::try_from(segment.archive_size).ok();
+
+        start
+            .zip(size)
+            .and_then(|(start, size)| self.source.as_ref().get(start..start.checked_add(size)?))
+            .ok_or_else(|| ErrorKind::UnexpectedEof.into())
+    }
+}
@@ -0,0 +1,27 @@
+use std::io::{self, BufRead, Read, Take};
+
+use flate2::bufread::ZlibDecoder;
+
+#[derive(Debug)]
+pub enum XP3Stream<T> {
+    Compressed(Take<ZlibDecoder<Take<T>>>),
+    Raw(Take<T>),
+}
+
+impl<T: BufRead> XP3Stream<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            XP3Stream::Compressed(stream) => stream.into_inner().into_inner().into_inner(),
+            XP3Stream::Raw(stream) => stream.into_inner(),
+        }
+    }
+}
+
+impl<T: BufRead> Read for XP3Stream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            XP3Stream::Compressed(stream) => stream.read(buf),
+            XP3Stream::Raw(stream) => stream.read(buf),
+        }
+    }
+}
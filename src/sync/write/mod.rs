@@ -0,0 +1,288 @@
+pub(crate) mod pack;
+mod stream;
+
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+
+use crate::{
+    entry::{DataSegment, XP3Entries, XP3FileEntry},
+    error::{XP3OpenError, poisoned},
+    header::{XP3Header, XP3Version},
+    options::XP3ArchiveOptions,
+    sync::{XP3Archive, write::stream::XP3FileStream},
+    writer::{FileWriterState, WriterState, file_writer_setters, writer_accessors},
+};
+
+pub use pack::XP3PackFile;
+
+#[derive(Debug)]
+pub struct XP3Writer<T> {
+    state: WriterState,
+    stream: T,
+}
+
+impl<T> XP3Writer<T>
+where
+    T: Write + Seek,
+{
+    pub fn new(version: XP3Version, mut stream: T) -> io::Result<Self> {
+        let start = stream.stream_position()?;
+        let (header, index_offset_pos) = version.encode_header();
+        stream.write_all(&header)?;
+        Ok(Self::from_parts(
+            start,
+            start + index_offset_pos,
+            XP3Entries::new(),
+            stream,
+        ))
+    }
+
+    writer_accessors!();
+
+    pub fn file(
+        &mut self,
+        name: String,
+        protected: bool,
+        compression: Option<u8>,
+    ) -> io::Result<XP3FileWriter<'_, T>> {
+        let segment_start = self.stream.stream_position()? - self.state.start;
+        Ok(XP3FileWriter {
+            file: self.state.file(name, protected, compression, segment_start),
+            stream: Some(XP3FileStream::new(
+                compression,
+                self.state.adaptive,
+                &mut self.stream,
+            )),
+            writer: &mut self.state,
+        })
+    }
+
+    /// Copy file at `index` of `archive` without decompressing or decrypting.
+    /// Segment data and metadata including checksum, timestamp, protected flag and compressed flags are kept as is.
+    /// Returns file index, [`None`] if `index` is out of range
+    pub fn copy_raw<R: BufRead + Seek>(
+        &mut self,
+        archive: &mut XP3Archive<R>,
+        index: usize,
+    ) -> Option<io::Result<usize>> {
+        let (entries, start, stream) = archive.raw_parts();
+        let entry = entries.entries.get(index)?.clone();
+        let segments = DataSegment::chain(&entries.segments, entries.file_starts[index])
+            .map(|(_, segment, _)| segment);
+        Some(self.push_raw(entry, segments, stream, start))
+    }
+
+    /// Keep unknown top level chunks of `archive`, their order and name chunk tag,
+    /// so an archive rewritten using [`XP3Writer::copy_raw`] keeps the index layout of the original
+    pub fn copy_layout<R: BufRead + Seek>(&mut self, archive: &mut XP3Archive<R>) {
+        let (entries, _, _) = archive.raw_parts();
+        self.keep_layout(entries);
+    }
+
+    /// Create writer adding files to `entries` of archive starting at `start`.
+    /// Files are written from current position of `stream`.
+    pub(crate) fn from_parts(
+        start: u64,
+        index_offset_pos: u64,
+        entries: XP3Entries,
+        stream: T,
+    ) -> Self {
+        Self {
+            state: WriterState::new(start, index_offset_pos, entries),
+            stream,
+        }
+    }
+
+    /// Keep unknown top level chunks, their order and name chunk tag of `entries`
+    pub(crate) fn keep_layout(&mut self, entries: &XP3Entries) {
+        self.state.entries.chunks = entries.chunks.clone();
+        self.state.entries.layout = entries.layout.clone();
+        self.state.entries.name_tag = entries.name_tag;
+    }
+
+    /// Add file with data already in archive
+    pub(crate) fn push_existing(
+        &mut self,
+        entry: XP3FileEntry,
+        segments: impl IntoIterator<Item = DataSegment>,
+    ) -> usize {
+        self.state.entries.push(entry, segments)
+    }
+
+    /// Add file sharing data of file at `index`
+    pub(crate) fn push_shared(&mut self, entry: XP3FileEntry, index: usize) -> usize {
+        self.state.entries.push_shared(entry, index)
+    }
+
+    /// Copy data of segments verbatim from archive stream `source` starting at `source_start` and add file.
+    /// Returns file index
+    pub(crate) fn push_raw(
+        &mut self,
+        entry: XP3FileEntry,
+        segments: impl IntoIterator<Item = DataSegment>,
+        source: &mut (impl Read + Seek),
+        source_start: u64,
+    ) -> io::Result<usize> {
+        let mut copied = vec![];
+        for segment in segments {
+            let start = self.stream.stream_position()? - self.state.start;
+            source.seek(SeekFrom::Start(segment.position(source_start, 0)?))?;
+            let size = io::copy(&mut source.take(segment.archive_size), &mut self.stream)?;
+            if size != segment.archive_size {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            copied.push(DataSegment { start, ..segment });
+        }
+
+        Ok(self.state.entries.push(entry, copied))
+    }
+
+    /// Write index right after the last file data and header.
+    /// Returns stream positioned at the end of archive.
+    ///
+    /// Data dropped by deduplication or an old index of appended archive may remain past the end of archive,
+    /// truncate the stream at its position after finish if needed.
+    pub fn finish(mut self, compression: Option<u8>) -> io::Result<T> {
+        let index = self.state.entries.encode(compression)?;
+        let index_start = self.stream.stream_position()?;
+        self.stream.write_all(&index)?;
+
+        let end = self.stream.stream_position()?;
+        self.stream
+            .seek(SeekFrom::Start(self.state.index_offset_pos))?;
+        self.stream
+            .write_all(&(index_start - self.state.start).to_le_bytes())?;
+        self.stream.seek(SeekFrom::Start(end))?;
+        self.stream.flush()?;
+        Ok(self.stream)
+    }
+}
+
+impl<T> XP3Writer<T>
+where
+    T: Read + Write + Seek,
+{
+    /// Open existing archive to add files.
+    /// Data of existing files is kept untouched and new files are written over the old index,
+    /// or after the end of stream if the old index is not placed after file data.
+    /// Index written on finish contains both existing and new files.
+    pub fn append(stream: T) -> Result<Self, XP3OpenError> {
+        Self::append_with(stream, &XP3ArchiveOptions::new())
+    }
+
+    pub(crate) fn append_with(
+        mut stream: T,
+        options: &XP3ArchiveOptions,
+    ) -> Result<Self, XP3OpenError> {
+        let start = stream.stream_position()?;
+        let header = XP3Header::read(&mut stream)?;
+
+        stream.seek(SeekFrom::Start(start + header.index_start))?;
+        let entries = XP3Entries::open(&mut stream, &options.limits)?;
+
+        stream.seek(SeekFrom::End(0))?;
+        if entries.data_end() <= header.index_start {
+            stream.seek(SeekFrom::Start(start + header.index_start))?;
+        }
+
+        let mut writer = Self::from_parts(start, start + header.index_offset_pos, entries, stream);
+        writer.state.filter = options.filter.clone();
+        Ok(writer)
+    }
+}
+
+#[must_use]
+pub struct XP3FileWriter<'a, T: Write> {
+    file: FileWriterState,
+    writer: &'a mut WriterState,
+    stream: Option<XP3FileStream<&'a mut T>>,
+}
+
+impl<'a, T> XP3FileWriter<'a, T>
+where
+    T: Write,
+{
+    file_writer_setters!();
+
+    /// Set original order of chunks in file index, see [`XP3FileEntry::chunk_order`]
+    pub(crate) fn chunk_order(&mut self, order: Vec<u32>) {
+        self.file.entry.chunk_order = order;
+    }
+
+    /// Finish current segment and start next one
+    pub fn next_segment(&mut self) -> io::Result<()> {
+        let stream = self.finish_segment()?;
+        self.stream = Some(XP3FileStream::new(
+            self.file.compression,
+            self.file.adaptive,
+            stream,
+        ));
+        Ok(())
+    }
+
+    /// Finish current segment and return inner stream
+    fn finish_segment(&mut self) -> io::Result<&'a mut T> {
+        let mut stream = self.stream.take().ok_or_else(poisoned)?;
+        stream.finish()?;
+
+        self.file.push_segment(
+            stream.compressed(),
+            stream.written_original(),
+            stream.written(),
+        );
+        stream.into_inner()
+    }
+}
+
+impl<T> XP3FileWriter<'_, T>
+where
+    T: Write + Seek,
+{
+    /// Finish and add file to archive.
+    /// Returns file index
+    pub fn finish(mut self) -> io::Result<usize> {
+        if let Some(data) = self.file.take_pending() {
+            self.write_all(&data)?;
+        }
+        let stream = self.finish_segment()?;
+
+        let (index, dropped) = self.file.finish(self.writer)?;
+        if let Some(size) = dropped {
+            stream.seek(SeekFrom::Current(-(size as i64)))?;
+        }
+
+        Ok(index)
+    }
+}
+
+impl<T> Write for XP3FileWriter<'_, T>
+where
+    T: Write,
+{
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        if self.file.buffering() {
+            self.file.buffer(buf);
+            return Ok(buf.len());
+        }
+
+        let mut written = self
+            .stream
+            .as_ref()
+            .ok_or_else(poisoned)?
+            .written_original();
+        if self.file.segment_full(written, buf.len()) {
+            self.next_segment()?;
+            written = 0;
+        }
+        buf = &buf[..self.file.segment_room(written, buf.len())];
+
+        let stream = self.stream.as_mut().ok_or_else(poisoned)?;
+        let written = stream.write(self.file.encrypt(buf))?;
+        self.file.advance(buf, written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.as_mut().ok_or_else(poisoned)?.flush()
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    borrow::Cow,
+    io::{self, Seek, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use adler32::RollingAdler32;
+use flate2::{Compression, write::ZlibEncoder};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    entry::{DataSegment, Dedup, XP3Entries, XP3FileEntry},
+    filter::XP3Filter,
+    sync::write::XP3Writer,
+};
+
+/// File packed by [`XP3Writer::pack`]
+#[derive(Debug, Clone, Default)]
+pub struct XP3PackFile {
+    pub name: String,
+    pub protected: bool,
+    pub timestamp: Option<u64>,
+    /// Compression level of file data
+    pub compression: Option<u8>,
+    pub data: Vec<u8>,
+}
+
+/// File prepared for writing, data is encrypted if filter is set
+struct Prepared<'a> {
+    entry: XP3FileEntry,
+    data: Cow<'a, [u8]>,
+    compression: Option<u8>,
+    /// Index of identical file if deduplicated
+    shared: Option<usize>,
+    /// Digest of data if deduplicating
+    digest: Option<[u8; 32]>,
+}
+
+/// Compressed segment, whether data is compressed, original size and compressed data if not stored as is
+type Compressed = io::Result<(bool, u64, Option<Vec<u8>>)>;
+
+/// Packed file ready to be written
+pub(crate) struct Packed<'a> {
+    pub entry: XP3FileEntry,
+    /// Index of identical file if deduplicated
+    pub shared: Option<usize>,
+    /// Digest of data recorded once the file is added if deduplicating
+    pub digest: Option<[u8; 32]>,
+    /// Whether data is compressed, original size and data to write of each segment
+    pub segments: Vec<(bool, u64, Cow<'a, [u8]>)>,
+}
+
+impl Packed<'_> {
+    #[cfg(feature = "tokio")]
+    /// Take ownership of borrowed segment data
+    pub fn into_owned(self) -> Packed<'static> {
+        Packed {
+            entry: self.entry,
+            shared: self.shared,
+            digest: self.digest,
+            segments: self
+                .segments
+                .into_iter()
+                .map(|(compressed, size, data)| (compressed, size, Cow::Owned(data.into_owned())))
+                .collect(),
+        }
+    }
+
+    /// Add file to `entries` after its segments are written at `start`
+    /// and record its content in `dedup`.
+    /// Returns file index
+    pub fn push(self, entries: &mut XP3Entries, dedup: Option<&mut Dedup>, start: u64) -> usize {
+        if let Some(index) = self.shared {
+            if let Some(dedup) = dedup {
+                dedup.add_saved(entries.entries[index].archive_size);
+            }
+            return entries.push_shared(self.entry, index);
+        }
+
+        let mut start = start;
+        let segments = self
+            .segments
+            .iter()
+            .map(|(compressed, size, data)| {
+                let segment = DataSegment {
+                    compressed: *compressed,
+                    start,
+                    size: *size,
+                    archive_size: data.len() as u64,
+                    next: None,
+                };
+                start += data.len() as u64;
+                segment
+            })
+            .collect::<Vec<_>>();
+
+        let entry = XP3FileEntry {
+            archive_size: segments.iter().map(|segment| segment.archive_size).sum(),
+            ..self.entry
+        };
+        let size = entry.size;
+        let index = entries.push(entry, segments);
+        if let Some(dedup) = dedup
+            && let Some(digest) = self.digest
+        {
+            dedup.find_or_insert(size, digest, index);
+        }
+        index
+    }
+}
+
+impl<T> XP3Writer<T>
+where
+    T: Write + Seek,
+{
+    /// Pack files compressing their segments on `threads` threads, zero uses available parallelism.
+    /// Files are taken from `files` lazily a few at a time and written in order,
+    /// so output is identical regardless of thread count.
+    /// Returns file indices
+    pub fn pack(
+        &mut self,
+        files: impl IntoIterator<Item = XP3PackFile>,
+        threads: usize,
+    ) -> io::Result<Vec<usize>> {
+        let threads = pack_threads(threads);
+        let mut files = files.into_iter();
+        let mut ids = vec![];
+        loop {
+            let window = files.by_ref().take(threads * 4).collect::<Vec<_>>();
+            if window.is_empty() {
+                break;
+            }
+
+            self.pack_window(&window, threads, &mut ids)?;
+        }
+
+        Ok(ids)
+    }
+
+    fn pack_window(
+        &mut self,
+        files: &[XP3PackFile],
+        threads: usize,
+        ids: &mut Vec<usize>,
+    ) -> io::Result<()> {
+        let packed = pack_files(
+            self.state.entries.entries.len(),
+            self.state.filter.as_ref(),
+            self.state.dedup.as_ref(),
+            files,
+            pack_segment_size(self.state.segment_size),
+            self.state.adaptive,
+            threads,
+        )?;
+
+        for file in packed {
+            let start = self.stream.stream_position()? - self.state.start;
+            for (_, _, data) in &file.segments {
+                self.stream.write_all(data)?;
+            }
+
+            ids.push(file.push(&mut self.state.entries, self.state.dedup.as_mut(), start));
+        }
+
+        Ok(())
+    }
+}
+
+/// Thread count of [`XP3Writer::pack`], zero uses available parallelism
+pub(crate) fn pack_threads(threads: usize) -> usize {
+    match threads {
+        0 => thread::available_parallelism().map_or(1, |threads| threads.get()),
+        threads => threads,
+    }
+}
+
+/// Segment size of packed files
+pub(crate) fn pack_segment_size(segment_size: Option<u64>) -> usize {
+    segment_size.map_or(usize::MAX, |size| {
+        usize::try_from(size).unwrap_or(usize::MAX)
+    })
+}
+
+/// Prepare and compress window of files on `threads` threads, `base` is index of the first file.
+/// Returns files in order
+pub(crate) fn pack_files<'a>(
+    base: usize,
+    filter: Option<&Arc<dyn XP3Filter>>,
+    dedup: Option<&Dedup>,
+    files: &'a [XP3PackFile],
+    segment_size: usize,
+    adaptive: bool,
+    threads: usize,
+) -> io::Result<Vec<Packed<'a>>> {
+    let prepared = prepare_window(base, filter, dedup, files);
+    let mut outputs = compress_window(&prepared, segment_size, adaptive, threads).into_iter();
+
+    let mut packed = Vec::with_capacity(prepared.len());
+    for file in prepared {
+        let count = match file.shared {
+            Some(_) => 0,
+            None => file.data.len().div_ceil(segment_size).max(1),
+        };
+
+        let mut segments = Vec::with_capacity(count);
+        let mut offset = 0;
+        for output in outputs.by_ref().take(count) {
+            let (compressed, size, data) = output?;
+            let range = offset..offset + size as usize;
+            offset = range.end;
+
+            // Stored segments borrow file data unless it is encrypted
+            let data = match (data, &file.data) {
+                (Some(data), _) => Cow::Owned(data),
+                (None, &Cow::Borrowed(data)) => Cow::Borrowed(&data[range]),
+                (None, Cow::Owned(data)) => Cow::Owned(data[range].to_vec()),
+            };
+            segments.push((compressed, size, data));
+        }
+
+        packed.push(Packed {
+            entry: file.entry,
+            shared: file.shared,
+            digest: file.digest,
+            segments,
+        });
+    }
+
+    Ok(packed)
+}
+
+/// Compute checksum, encrypt data and find identical file of every file in window.
+/// Files are recorded in `dedup` only once added, see [`Packed::push`]
+fn prepare_window<'a>(
+    base: usize,
+    filter: Option<&Arc<dyn XP3Filter>>,
+    dedup: Option<&Dedup>,
+    files: &'a [XP3PackFile],
+) -> Vec<Prepared<'a>> {
+    // Contents of earlier files in window
+    let mut window = Dedup::default();
+    files
+        .iter()
+        .enumerate()
+        .map(|(i, file)| {
+            let mut checksum = RollingAdler32::new();
+            checksum.update_buffer(&file.data);
+
+            let entry = XP3FileEntry {
+                protected: file.protected,
+                name: file.name.clone(),
+                size: file.data.len() as u64,
+                checksum: checksum.hash(),
+                timestamp: file.timestamp,
+                ..Default::default()
+            };
+
+            let data = match filter {
+                Some(filter) => {
+                    let mut data = file.data.clone();
+                    filter.encrypt(&entry, 0, &mut data);
+                    Cow::Owned(data)
+                }
+                None => Cow::Borrowed(&file.data[..]),
+            };
+
+            let digest = dedup.map(|_| Sha256::digest(&data).into());
+            let shared = dedup.zip(digest).and_then(|(dedup, digest)| {
+                dedup
+                    .find(entry.size, &digest)
+                    .or_else(|| window.find_or_insert(entry.size, digest, base + i))
+            });
+
+            Prepared {
+                entry,
+                data,
+                compression: file.compression,
+                shared,
+                digest,
+            }
+        })
+        .collect()
+}
+
+/// Compress segments of files not deduplicated on `threads` threads.
+/// Returns outputs in order of files and segments
+fn compress_window(
+    prepared: &[Prepared],
+    segment_size: usize,
+    adaptive: bool,
+    threads: usize,
+) -> Vec<Compressed> {
+    // Split every file into segment jobs, empty files still have a segment
+    let jobs = prepared
+        .iter()
+        .filter(|file| file.shared.is_none())
+        .flat_map(|file| {
+            file.data
+                .chunks(segment_size)
+                .chain(file.data.is_empty().then_some(&[][..]))
+                .map(|data| (data, file.compression))
+        })
+        .collect::<Vec<_>>();
+
+    let next = AtomicUsize::new(0);
+    let mut outputs = thread::scope(|scope| {
+        let workers = (0..threads.min(jobs.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut outputs = vec![];
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&(data, compression)) = jobs.get(index) else {
+                            break;
+                        };
+
+                        outputs.push((index, compress(data, compression, adaptive)));
+                    }
+
+                    outputs
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut outputs = Vec::with_capacity(jobs.len());
+        for worker in workers {
+            outputs.extend(worker.join().expect("compression worker panicked"));
+        }
+        outputs
+    });
+    outputs.sort_unstable_by_key(|&(index, _)| index);
+
+    outputs.into_iter().map(|(_, output)| output).collect()
+}
+
+/// Compress segment data.
+/// Returns whether data is compressed, original size and compressed data if not stored as is
+fn compress(data: &[u8], compression: Option<u8>, adaptive: bool) -> Compressed {
+    let Some(level) = compression else {
+        return Ok((false, data.len() as _, None));
+    };
+
+    let mut encoder = ZlibEncoder::new(vec![], Compression::new(level as _));
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+    if adaptive && compressed.len() >= data.len() {
+        Ok((false, data.len() as _, None))
+    } else {
+        Ok((true, data.len() as _, Some(compressed)))
+    }
+}
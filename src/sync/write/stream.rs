@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+
+use flate2::{Compression, write::ZlibEncoder};
+
+#[derive(Debug)]
+pub enum XP3FileStream<T: Write> {
+    Compressed(ZlibEncoder<T>),
+    Raw {
+        written: u64,
+        stream: T,
+    },
+    /// Segment data is buffered and compressed on finish.
+    /// Stored uncompressed if compression does not reduce size.
+    Adaptive {
+        level: u8,
+        buf: Vec<u8>,
+        original: u64,
+        compressed: Option<bool>,
+        stream: T,
+    },
+}
+
+impl<T: Write> XP3FileStream<T> {
+    pub fn new(compression: Option<u8>, adaptive: bool, stream: T) -> Self {
+        match compression {
+            Some(level) if adaptive => XP3FileStream::Adaptive {
+                level,
+                buf: vec![],
+                original: 0,
+                compressed: None,
+                stream,
+            },
+            Some(level) => {
+                XP3FileStream::Compressed(ZlibEncoder::new(stream, Compression::new(level as _)))
+            }
+            None => XP3FileStream::Raw { written: 0, stream },
+        }
+    }
+
+    pub fn compressed(&self) -> bool {
+        matches!(
+            self,
+            XP3FileStream::Compressed(_)
+                | XP3FileStream::Adaptive {
+                    compressed: Some(true),
+                    ..
+                }
+        )
+    }
+
+    pub fn written(&self) -> u64 {
+        match *self {
+            XP3FileStream::Compressed(ref stream) => stream.total_out(),
+            XP3FileStream::Raw { written, .. } => written,
+            XP3FileStream::Adaptive {
+                ref buf,
+                compressed,
+                ..
+            } => {
+                if compressed.is_some() {
+                    buf.len() as _
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    pub fn written_original(&self) -> u64 {
+        match *self {
+            XP3FileStream::Compressed(ref stream) => stream.total_in(),
+            XP3FileStream::Raw { written, .. } => written,
+            XP3FileStream::Adaptive {
+                ref buf,
+                original,
+                compressed,
+                ..
+            } => {
+                if compressed.is_some() {
+                    original
+                } else {
+                    buf.len() as _
+                }
+            }
+        }
+    }
+
+    /// Finish remaining data and flush
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            XP3FileStream::Compressed(stream) => stream.try_finish()?,
+            XP3FileStream::Raw { stream, .. } => stream.flush()?,
+            XP3FileStream::Adaptive {
+                level,
+                buf,
+                original,
+                compressed,
+                stream,
+            } => {
+                if compressed.is_none() {
+                    *original = buf.len() as _;
+
+                    let mut encoder = ZlibEncoder::new(vec![], Compression::new(*level as _));
+                    encoder.write_all(buf)?;
+                    let data = encoder.finish()?;
+                    if data.len() < buf.len() {
+                        *buf = data;
+                        *compressed = Some(true);
+                    } else {
+                        *compressed = Some(false);
+                    }
+
+                    stream.write_all(buf)?;
+                }
+
+                stream.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finish remaining data and return inner stream
+    pub fn into_inner(self) -> io::Result<T> {
+        match self {
+            XP3FileStream::Compressed(stream) => stream.finish(),
+            XP3FileStream::Raw { stream, .. } | XP3FileStream::Adaptive { stream, .. } => {
+                Ok(stream)
+            }
+        }
+    }
+}
+
+impl<T: Write> Write for XP3FileStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            XP3FileStream::Compressed(stream) => stream.write(buf),
+            XP3FileStream::Raw { stream, written } => {
+                let written_size = stream.write(buf)?;
+                *written += written_size as u64;
+                Ok(written_size)
+            }
+            XP3FileStream::Adaptive { buf: data, .. } => {
+                data.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            XP3FileStream::Compressed(stream) => stream.flush(),
+            XP3FileStream::Raw { stream, .. } => stream.flush(),
+            XP3FileStream::Adaptive { .. } => Ok(()),
+        }
+    }
+}
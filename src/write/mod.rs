@@ -1,27 +1,30 @@
+mod pack;
 mod stream;
 
 use core::{
+    future::poll_fn,
     pin::Pin,
     task::{Context, Poll, ready},
 };
 use std::io::{self, SeekFrom};
 
-use adler32::RollingAdler32;
-use async_compression::{Level, tokio::write::ZlibEncoder};
-use tokio::io::{AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{
+    AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt,
+};
 
 use crate::{
-    XP3_CURRENT_VER_IDENTIFIER, XP3_MAGIC, XP3_VERSION_IDENTIFIER,
-    entry::{DataSegment, XP3Entries, XP3FileEntry},
-    header::XP3Version,
+    entry::{DataSegment, XP3Entries},
+    error::{XP3OpenError, poisoned},
+    header::{XP3Header, XP3Version},
+    options::XP3ArchiveOptions,
+    read::XP3Archive,
     write::stream::XP3FileStream,
+    writer::{FileWriterState, WriterState, file_writer_setters, writer_accessors},
 };
 
 #[derive(Debug)]
 pub struct XP3Writer<T> {
-    start: u64,
-    index_offset_pos: u64,
-    entries: XP3Entries,
+    state: WriterState,
     stream: T,
 }
 
@@ -31,138 +34,198 @@ where
 {
     pub async fn new(version: XP3Version, mut stream: T) -> io::Result<Self> {
         let start = stream.stream_position().await?;
-        // write magic
-        stream.write_all(&XP3_MAGIC).await?;
-        stream.write_u8(1).await?;
-
-        let index_offset_pos = match version {
-            XP3Version::Old => {
-                let pos = stream.stream_position().await?;
-                // placeholder index offset value
-                stream.write_u64_le(0).await?;
-                pos
-            }
-            XP3Version::Current { minor } => {
-                stream.write_u64_le(XP3_CURRENT_VER_IDENTIFIER).await?;
-                stream.write_u32_le(minor).await?;
-                stream.write_u8(XP3_VERSION_IDENTIFIER).await?;
-                stream.write_u64_le(0).await?;
-                let pos = stream.stream_position().await?;
-                // placeholder index offset value
-                stream.write_u64_le(0).await?;
-                pos
-            }
-        };
+        let (header, index_offset_pos) = version.encode_header();
+        stream.write_all(&header).await?;
 
         Ok(Self {
-            start,
-            index_offset_pos,
-            entries: XP3Entries::new(),
+            state: WriterState::new(start, start + index_offset_pos, XP3Entries::new()),
             stream,
         })
     }
 
+    writer_accessors!();
+
+    /// Start writing file.
+    /// Segments of files with adaptive compression are compressed with [`tokio::task::spawn_blocking`],
+    /// so they must be written inside a tokio runtime.
     pub async fn file<'a>(
         &'a mut self,
         name: String,
         protected: bool,
         compression: Option<u8>,
     ) -> io::Result<XP3FileWriter<'a, T>> {
-        let file_start = self.stream.stream_position().await? - self.start;
-        let stream = match compression {
-            Some(level) => XP3FileStream::Compressed(ZlibEncoder::with_quality(
+        let segment_start = self.stream.stream_position().await? - self.state.start;
+        Ok(XP3FileWriter {
+            file: self.state.file(name, protected, compression, segment_start),
+            stream: Some(XP3FileStream::new(
+                compression,
+                self.state.adaptive,
                 &mut self.stream,
-                Level::Precise(level as _),
             )),
-            None => XP3FileStream::Raw {
-                written: 0,
-                stream: &mut self.stream,
-            },
-        };
-
-        Ok(XP3FileWriter {
-            protected,
-            name,
-            timestamp: None,
-            file_start,
-            compressed: compression.is_some(),
-            checksum: RollingAdler32::new(),
-            entries: &mut self.entries,
-            stream,
+            writer: &mut self.state,
         })
     }
 
+    /// Copy file at `index` of `archive` without decompressing or decrypting.
+    /// Segment data and metadata including checksum, timestamp, protected flag and compressed flags are kept as is.
+    /// Returns file index, [`None`] if `index` is out of range
+    pub async fn copy_raw<R: AsyncBufRead + AsyncSeek + Unpin>(
+        &mut self,
+        archive: &mut XP3Archive<R>,
+        index: usize,
+    ) -> Option<io::Result<usize>> {
+        let (entries, source_start, source) = archive.raw_parts();
+        let entry = entries.entries.get(index)?.clone();
+        let segments = DataSegment::chain(&entries.segments, entries.file_starts[index])
+            .map(|(_, segment, _)| segment)
+            .collect::<Vec<_>>();
+
+        Some(
+            async {
+                let mut copied = vec![];
+                for segment in segments {
+                    let start = self.stream.stream_position().await? - self.state.start;
+                    source
+                        .seek(SeekFrom::Start(segment.position(source_start, 0)?))
+                        .await?;
+                    let size = tokio::io::copy(
+                        &mut (&mut *source).take(segment.archive_size),
+                        &mut self.stream,
+                    )
+                    .await?;
+                    if size != segment.archive_size {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+
+                    copied.push(DataSegment { start, ..segment });
+                }
+
+                Ok(self.state.entries.push(entry, copied))
+            }
+            .await,
+        )
+    }
+
+    /// Write index and header.
+    /// See [`crate::sync::XP3Writer::finish`]
     pub async fn finish(mut self, compression: Option<u8>) -> io::Result<T> {
+        let index = self.state.entries.encode(compression)?;
         let index_start = self.stream.stream_position().await?;
-        self.entries.write(compression, &mut self.stream).await?;
+        self.stream.write_all(&index).await?;
 
         let end = self.stream.stream_position().await?;
         self.stream
-            .seek(SeekFrom::Start(self.index_offset_pos))
+            .seek(SeekFrom::Start(self.state.index_offset_pos))
+            .await?;
+        self.stream
+            .write_u64_le(index_start - self.state.start)
             .await?;
-        self.stream.write_u64_le(index_start - self.start).await?;
         self.stream.seek(SeekFrom::Start(end)).await?;
         self.stream.flush().await?;
         Ok(self.stream)
     }
 }
+impl<T> XP3Writer<T>
+where
+    T: AsyncRead + AsyncWrite + AsyncSeek + Unpin,
+{
+    /// Open existing archive to add files.
+    /// Data of existing files is kept untouched and new files are written over the old index,
+    /// or after the end of stream if the old index is not placed after file data.
+    /// Index written on finish contains both existing and new files.
+    pub async fn append(stream: T) -> Result<Self, XP3OpenError> {
+        Self::append_with(stream, &XP3ArchiveOptions::new()).await
+    }
+
+    pub(crate) async fn append_with(
+        mut stream: T,
+        options: &XP3ArchiveOptions,
+    ) -> Result<Self, XP3OpenError> {
+        let start = stream.stream_position().await?;
+        let header = XP3Header::read_async(&mut stream).await?;
+
+        stream
+            .seek(SeekFrom::Start(start + header.index_start))
+            .await?;
+        let entries = XP3Entries::open_async(&mut stream, &options.limits).await?;
+
+        stream.seek(SeekFrom::End(0)).await?;
+        if entries.data_end() <= header.index_start {
+            stream
+                .seek(SeekFrom::Start(start + header.index_start))
+                .await?;
+        }
+
+        let mut state = WriterState::new(start, start + header.index_offset_pos, entries);
+        state.filter = options.filter.clone();
+        Ok(Self { state, stream })
+    }
+}
+
 #[must_use]
 pub struct XP3FileWriter<'a, T> {
-    protected: bool,
-    name: String,
-    timestamp: Option<u64>,
-    file_start: u64,
-    compressed: bool,
-    entries: &'a mut XP3Entries,
-    checksum: RollingAdler32,
-    stream: XP3FileStream<&'a mut T>,
+    file: FileWriterState,
+    writer: &'a mut WriterState,
+    stream: Option<XP3FileStream<&'a mut T>>,
 }
 
 impl<'a, T> XP3FileWriter<'a, T>
 where
     T: AsyncWrite + Unpin,
 {
-    /// Set file protected flag
-    pub fn protected(&mut self, protected: bool) {
-        self.protected = protected;
+    file_writer_setters!();
+
+    /// Finish current segment and start next one
+    pub async fn next_segment(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.poll_next_segment(cx)).await
     }
 
-    /// Set file name
-    pub fn name(&mut self, name: String) {
-        self.name = name;
+    fn poll_next_segment(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let stream = ready!(self.poll_finish_segment(cx))?;
+        self.stream = Some(XP3FileStream::new(
+            self.file.compression,
+            self.file.adaptive,
+            stream,
+        ));
+        Poll::Ready(Ok(()))
     }
 
-    /// Set file timestamp
-    pub fn timestamp(&mut self, timestamp: Option<u64>) {
-        self.timestamp = timestamp;
+    /// Finish current segment and return inner stream
+    fn poll_finish_segment(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&'a mut T>> {
+        let stream = self.stream.as_mut().ok_or_else(poisoned)?;
+        if let Err(err) = ready!(Pin::new(&mut *stream).poll_shutdown(cx)) {
+            self.stream = None;
+            return Poll::Ready(Err(err));
+        }
+
+        let stream = self.stream.take().unwrap();
+        self.file.push_segment(
+            stream.compressed(),
+            stream.written_original(),
+            stream.written(),
+        );
+        Poll::Ready(Ok(stream.into_inner()))
     }
+}
 
+impl<T> XP3FileWriter<'_, T>
+where
+    T: AsyncWrite + AsyncSeek + Unpin,
+{
     /// Finish and add file to archive.
     /// Returns file index
     pub async fn finish(mut self) -> io::Result<usize> {
-        self.stream.shutdown().await?;
-        let size = self.stream.written_original();
-        let archive_size = self.stream.written();
-
-        let start_segment = self.entries.segments.len();
-        self.entries.segments.push(DataSegment {
-            compressed: self.compressed,
-            start: self.file_start,
-            size,
-            archive_size,
-            next: None,
-        });
-        let id = self.entries.entries.len();
-        self.entries.entries.push(XP3FileEntry {
-            protected: self.protected,
-            name: self.name,
-            size,
-            archive_size,
-            checksum: self.checksum.hash(),
-            timestamp: self.timestamp,
-        });
-        self.entries.file_starts.push(start_segment);
-        Ok(id)
+        if let Some(data) = self.file.take_pending() {
+            self.write_all(&data).await?;
+        }
+        let stream = poll_fn(|cx| self.poll_finish_segment(cx)).await?;
+
+        let (index, dropped) = self.file.finish(self.writer)?;
+        if let Some(size) = dropped {
+            stream.seek(SeekFrom::Current(-(size as i64))).await?;
+        }
+
+        Ok(index)
     }
 }
 
@@ -173,18 +236,38 @@ where
     fn poll_write(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-        buf: &[u8],
+        mut buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        let written = ready!(Pin::new(&mut self.stream).poll_write(cx, buf))?;
-        self.checksum.update_buffer(&buf[..written]);
+        let this = &mut *self;
+        if this.file.buffering() {
+            this.file.buffer(buf);
+            return Poll::Ready(Ok(buf.len()));
+        }
+
+        let mut written = this
+            .stream
+            .as_ref()
+            .ok_or_else(poisoned)?
+            .written_original();
+        if this.file.segment_full(written, buf.len()) {
+            ready!(this.poll_next_segment(cx))?;
+            written = 0;
+        }
+        buf = &buf[..this.file.segment_room(written, buf.len())];
+
+        let stream = this.stream.as_mut().ok_or_else(poisoned)?;
+        let written = ready!(Pin::new(stream).poll_write(cx, this.file.encrypt(buf)))?;
+        this.file.advance(buf, written);
         Poll::Ready(Ok(written))
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.stream).poll_flush(cx)
+        let stream = self.stream.as_mut().ok_or_else(poisoned)?;
+        Pin::new(stream).poll_flush(cx)
     }
 
     fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.stream).poll_shutdown(cx)
+        let stream = self.stream.as_mut().ok_or_else(poisoned)?;
+        Pin::new(stream).poll_shutdown(cx)
     }
 }
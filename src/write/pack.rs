@@ -0,0 +1,89 @@
+use std::{io, sync::Arc};
+
+use tokio::{
+    io::{AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    task,
+};
+
+use crate::{
+    sync::write::{
+        XP3PackFile,
+        pack::{pack_files, pack_segment_size, pack_threads},
+    },
+    write::XP3Writer,
+};
+
+impl<T> XP3Writer<T>
+where
+    T: AsyncWrite + AsyncSeek + Unpin,
+{
+    /// Pack files compressing their segments on `threads` threads, zero uses available parallelism.
+    /// Output is identical to [`crate::sync::XP3Writer::pack`] with the same files and settings.
+    /// Each window of files is compressed with [`task::spawn_blocking`], so it must be called inside a tokio runtime.
+    /// Returns file indices
+    pub async fn pack(
+        &mut self,
+        files: impl IntoIterator<Item = XP3PackFile>,
+        threads: usize,
+    ) -> io::Result<Vec<usize>> {
+        let threads = pack_threads(threads);
+        let mut files = files.into_iter();
+        let mut ids = vec![];
+        loop {
+            let window = files.by_ref().take(threads * 4).collect::<Vec<_>>();
+            if window.is_empty() {
+                break;
+            }
+
+            self.pack_window(window, threads, &mut ids).await?;
+        }
+
+        Ok(ids)
+    }
+
+    async fn pack_window(
+        &mut self,
+        files: Vec<XP3PackFile>,
+        threads: usize,
+        ids: &mut Vec<usize>,
+    ) -> io::Result<()> {
+        let base = self.state.entries.entries.len();
+        let filter = self.state.filter.clone();
+        let segment_size = pack_segment_size(self.state.segment_size);
+        let adaptive = self.state.adaptive;
+        // Deduplication state is shared with the blocking task and put back even if it fails
+        let dedup = self.state.dedup.take().map(Arc::new);
+        let task_dedup = dedup.clone();
+        let packed = task::spawn_blocking(move || {
+            pack_files(
+                base,
+                filter.as_ref(),
+                task_dedup.as_deref(),
+                &files,
+                segment_size,
+                adaptive,
+                threads,
+            )
+            .map(|packed| {
+                packed
+                    .into_iter()
+                    .map(|file| file.into_owned())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .await;
+        self.state.dedup =
+            dedup.map(|dedup| Arc::try_unwrap(dedup).unwrap_or_else(|dedup| (*dedup).clone()));
+
+        for file in packed.map_err(io::Error::other)?? {
+            let start = self.stream.stream_position().await? - self.state.start;
+            for (_, _, data) in &file.segments {
+                self.stream.write_all(data).await?;
+            }
+
+            ids.push(file.push(&mut self.state.entries, self.state.dedup.as_mut(), start));
+        }
+
+        Ok(())
+    }
+}
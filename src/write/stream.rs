@@ -1,12 +1,17 @@
 use core::{
+    mem,
     pin::Pin,
     task::{Context, Poll, ready},
 };
-use std::io;
+use std::io::{self, Write};
 
-use async_compression::tokio::write::ZlibEncoder;
+use async_compression::{Level, tokio::write::ZlibEncoder};
+use flate2::{Compression, write::ZlibEncoder as BlockingZlibEncoder};
 use pin_project::pin_project;
-use tokio::io::AsyncWrite;
+use tokio::{io::AsyncWrite, task::JoinHandle};
+
+/// Compression of buffered segment data in progress, returns data to write and whether it is compressed
+type CompressTask = JoinHandle<io::Result<(Vec<u8>, bool)>>;
 
 #[derive(Debug)]
 #[pin_project(project = XP3StreamProj)]
@@ -17,13 +22,73 @@ pub enum XP3FileStream<T> {
         #[pin]
         stream: T,
     },
+    /// Segment data is buffered and compressed on shutdown with [`tokio::task::spawn_blocking`].
+    /// Stored uncompressed if compression does not reduce size.
+    Adaptive {
+        level: u8,
+        buf: Vec<u8>,
+        original: u64,
+        task: Option<CompressTask>,
+        compressed: Option<bool>,
+        written: usize,
+        #[pin]
+        stream: T,
+    },
 }
 
 impl<T: AsyncWrite> XP3FileStream<T> {
+    pub fn new(compression: Option<u8>, adaptive: bool, stream: T) -> Self {
+        match compression {
+            Some(level) if adaptive => XP3FileStream::Adaptive {
+                level,
+                buf: vec![],
+                original: 0,
+                task: None,
+                compressed: None,
+                written: 0,
+                stream,
+            },
+            Some(level) => XP3FileStream::Compressed(ZlibEncoder::with_quality(
+                stream,
+                Level::Precise(level as _),
+            )),
+            None => XP3FileStream::Raw { written: 0, stream },
+        }
+    }
+
+    pub fn compressed(&self) -> bool {
+        matches!(
+            self,
+            XP3FileStream::Compressed(_)
+                | XP3FileStream::Adaptive {
+                    compressed: Some(true),
+                    ..
+                }
+        )
+    }
+
+    pub fn into_inner(self) -> T {
+        match self {
+            XP3FileStream::Compressed(stream) => stream.into_inner(),
+            XP3FileStream::Raw { stream, .. } | XP3FileStream::Adaptive { stream, .. } => stream,
+        }
+    }
+
     pub fn written(&self) -> u64 {
         match *self {
             XP3FileStream::Compressed(ref stream) => stream.total_out(),
             XP3FileStream::Raw { written, .. } => written,
+            XP3FileStream::Adaptive {
+                ref buf,
+                compressed,
+                ..
+            } => {
+                if compressed.is_some() {
+                    buf.len() as _
+                } else {
+                    0
+                }
+            }
         }
     }
 
@@ -31,6 +96,19 @@ impl<T: AsyncWrite> XP3FileStream<T> {
         match *self {
             XP3FileStream::Compressed(ref stream) => stream.total_in(),
             XP3FileStream::Raw { written, .. } => written,
+            XP3FileStream::Adaptive {
+                ref buf,
+                original,
+                ref task,
+                compressed,
+                ..
+            } => {
+                if task.is_some() || compressed.is_some() {
+                    original
+                } else {
+                    buf.len() as _
+                }
+            }
         }
     }
 }
@@ -48,6 +126,10 @@ impl<T: AsyncWrite> AsyncWrite for XP3FileStream<T> {
                 *written += written_size as u64;
                 Poll::Ready(Ok(written_size))
             }
+            XP3StreamProj::Adaptive { buf: data, .. } => {
+                data.extend_from_slice(buf);
+                Poll::Ready(Ok(buf.len()))
+            }
         }
     }
 
@@ -55,6 +137,7 @@ impl<T: AsyncWrite> AsyncWrite for XP3FileStream<T> {
         match self.project() {
             XP3StreamProj::Compressed(stream) => stream.poll_flush(cx),
             XP3StreamProj::Raw { stream, .. } => stream.poll_flush(cx),
+            XP3StreamProj::Adaptive { .. } => Poll::Ready(Ok(())),
         }
     }
 
@@ -62,6 +145,50 @@ impl<T: AsyncWrite> AsyncWrite for XP3FileStream<T> {
         match self.project() {
             XP3StreamProj::Compressed(stream) => stream.poll_shutdown(cx),
             XP3StreamProj::Raw { stream, .. } => stream.poll_shutdown(cx),
+            XP3StreamProj::Adaptive {
+                level,
+                buf,
+                original,
+                task,
+                compressed,
+                written,
+                mut stream,
+            } => {
+                if compressed.is_none() {
+                    let handle = task.get_or_insert_with(|| {
+                        *original = buf.len() as _;
+                        let data = mem::take(buf);
+                        let level = *level;
+                        tokio::task::spawn_blocking(move || {
+                            let mut encoder =
+                                BlockingZlibEncoder::new(vec![], Compression::new(level as _));
+                            encoder.write_all(&data)?;
+                            let compressed = encoder.finish()?;
+                            if compressed.len() < data.len() {
+                                Ok((compressed, true))
+                            } else {
+                                Ok((data, false))
+                            }
+                        })
+                    });
+
+                    let res = ready!(Pin::new(handle).poll(cx)).map_err(io::Error::other);
+                    *task = None;
+                    let (data, is_compressed) = res??;
+                    *buf = data;
+                    *compressed = Some(is_compressed);
+                }
+
+                while *written < buf.len() {
+                    let written_size = ready!(stream.as_mut().poll_write(cx, &buf[*written..]))?;
+                    if written_size == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                    }
+                    *written += written_size;
+                }
+
+                stream.poll_shutdown(cx)
+            }
         }
     }
 }
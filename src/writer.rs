@@ -0,0 +1,369 @@
+//! Index and file writing state shared by writers of every api
+
+use core::mem;
+use std::{io, sync::Arc};
+
+use adler32::RollingAdler32;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    entry::{DataSegment, Dedup, XP3Entries, XP3FileEntry},
+    filter::XP3Filter,
+};
+
+/// Segment size of files with adaptive compression and no segment size set,
+/// bounding memory used to buffer a segment
+pub(crate) const ADAPTIVE_SEGMENT_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Index of an archive being written with settings of new files
+#[derive(Debug)]
+pub(crate) struct WriterState {
+    /// Offset of the archive in its stream
+    pub start: u64,
+    /// Stream position of index offset in header
+    pub index_offset_pos: u64,
+    pub entries: XP3Entries,
+    pub filter: Option<Arc<dyn XP3Filter>>,
+    pub segment_size: Option<u64>,
+    pub adaptive: bool,
+    pub dedup: Option<Dedup>,
+}
+
+impl WriterState {
+    pub const fn new(start: u64, index_offset_pos: u64, entries: XP3Entries) -> Self {
+        Self {
+            start,
+            index_offset_pos,
+            entries,
+            filter: None,
+            segment_size: None,
+            adaptive: false,
+            dedup: None,
+        }
+    }
+
+    /// Start writing file at `segment_start` relative to the archive start
+    pub fn file(
+        &self,
+        name: String,
+        protected: bool,
+        compression: Option<u8>,
+        segment_start: u64,
+    ) -> FileWriterState {
+        FileWriterState {
+            entry: XP3FileEntry {
+                protected,
+                name,
+                ..Default::default()
+            },
+            expected_checksum: None,
+            filter: self.filter.clone(),
+            compression,
+            adaptive: self.adaptive,
+            segment_size: self.segment_size,
+            segment_start,
+            segments: vec![],
+            hasher: self.dedup.is_some().then(Sha256::new),
+            checksum: RollingAdler32::new(),
+            pos: 0,
+            buf: vec![],
+            pending: vec![],
+        }
+    }
+}
+
+/// Public accessors of writer types delegating to their `state` field
+macro_rules! writer_accessors {
+    () => {
+        /// Add top level index chunk written as is
+        pub fn push_chunk(&mut self, chunk: $crate::XP3Chunk) {
+            self.state.entries.chunks.push(chunk);
+        }
+
+        #[inline]
+        /// Filter applied to new files by default
+        pub fn filter(&self) -> Option<&::std::sync::Arc<dyn $crate::filter::XP3Filter>> {
+            self.state.filter.as_ref()
+        }
+
+        /// Set filter applied to new files by default
+        pub fn set_filter(
+            &mut self,
+            filter: Option<::std::sync::Arc<dyn $crate::filter::XP3Filter>>,
+        ) {
+            self.state.filter = filter;
+        }
+
+        #[inline]
+        /// Segment size of new files by default
+        pub const fn segment_size(&self) -> Option<u64> {
+            self.state.segment_size
+        }
+
+        /// Set segment size of new files by default.
+        /// Zero is treated as unlimited.
+        pub fn set_segment_size(&mut self, size: Option<u64>) {
+            self.state.segment_size = size.filter(|&size| size > 0);
+        }
+
+        #[inline]
+        /// Whether new files use adaptive compression by default
+        pub const fn adaptive_compression(&self) -> bool {
+            self.state.adaptive
+        }
+
+        /// Set whether new files use adaptive compression by default.
+        /// Each segment is buffered in memory until it is finished, so segments of files without segment size
+        /// are split every 4 MiB to bound memory use.
+        pub fn set_adaptive_compression(&mut self, adaptive: bool) {
+            self.state.adaptive = adaptive;
+        }
+
+        #[inline]
+        /// Whether files with content identical to a previous file are deduplicated
+        pub const fn deduplicate(&self) -> bool {
+            self.state.dedup.is_some()
+        }
+
+        /// Set whether files with content identical to a previous file are deduplicated.
+        /// Data of such files is dropped and their index points at the data of the previous file.
+        /// Only files finished while enabled are considered.
+        /// Dropped data is overwritten by following files and the index,
+        /// so the archive ends where its last file data ends, see [`finish`](Self::finish).
+        pub fn set_deduplicate(&mut self, deduplicate: bool) {
+            if !deduplicate {
+                self.state.dedup = None;
+            } else if self.state.dedup.is_none() {
+                self.state.dedup = Some(::core::default::Default::default());
+            }
+        }
+
+        #[inline]
+        /// Total bytes of file data saved by deduplication
+        pub fn deduplicated_size(&self) -> u64 {
+            self.state.dedup.as_ref().map_or(0, |dedup| dedup.saved())
+        }
+    };
+}
+
+pub(crate) use writer_accessors;
+
+/// Metadata, checksum and segments of a file being written, independent of the stream it is written to
+pub(crate) struct FileWriterState {
+    pub entry: XP3FileEntry,
+    pub expected_checksum: Option<u32>,
+    pub filter: Option<Arc<dyn XP3Filter>>,
+    pub compression: Option<u8>,
+    pub adaptive: bool,
+    pub segment_size: Option<u64>,
+    segment_start: u64,
+    segments: Vec<DataSegment>,
+    /// Digest of written data if deduplicated
+    hasher: Option<Sha256>,
+    checksum: RollingAdler32,
+    pos: u64,
+    buf: Vec<u8>,
+    /// Data buffered until checksum is known
+    pending: Vec<u8>,
+}
+
+impl FileWriterState {
+    /// Set adler32 checksum of file data known in advance
+    pub fn set_checksum(&mut self, checksum: Option<u32>) {
+        self.expected_checksum = checksum;
+        self.entry.checksum = checksum.unwrap_or_default();
+    }
+
+    /// Whether data is buffered since filter needs checksum not known yet
+    pub fn buffering(&self) -> bool {
+        !self.pending.is_empty()
+            || self.expected_checksum.is_none()
+                && self
+                    .filter
+                    .as_ref()
+                    .is_some_and(|filter| filter.needs_checksum())
+    }
+
+    /// Buffer data until checksum is known
+    pub fn buffer(&mut self, buf: &[u8]) {
+        self.pending.extend_from_slice(buf);
+    }
+
+    /// Take buffered data and set its checksum, so it can be written
+    pub fn take_pending(&mut self) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        let data = mem::take(&mut self.pending);
+        self.set_checksum(Some(RollingAdler32::from_buffer(&data).hash()));
+        Some(data)
+    }
+
+    /// Maximum size of segment data before compression.
+    /// Adaptively compressed segments are buffered in memory, so they are bounded even if no size is set
+    fn segment_limit(&self) -> Option<u64> {
+        self.segment_size
+            .or((self.adaptive && self.compression.is_some()).then_some(ADAPTIVE_SEGMENT_SIZE))
+    }
+
+    /// Whether a new segment must be started before writing `len` bytes
+    /// to a segment with `written` bytes of original data
+    pub fn segment_full(&self, written: u64, len: usize) -> bool {
+        self.segment_limit()
+            .is_some_and(|segment_size| written >= segment_size && len > 0)
+    }
+
+    /// Bytes of `len` fitting in a segment with `written` bytes of original data
+    pub fn segment_room(&self, written: u64, len: usize) -> usize {
+        match self.segment_limit() {
+            Some(segment_size) => segment_size.saturating_sub(written).min(len as u64) as usize,
+            None => len,
+        }
+    }
+
+    /// Data to write for `buf`, encrypted if filter is set
+    pub fn encrypt<'a>(&'a mut self, buf: &'a [u8]) -> &'a [u8] {
+        match self.filter {
+            Some(ref filter) => {
+                self.buf.clear();
+                self.buf.extend_from_slice(buf);
+                filter.encrypt(&self.entry, self.pos, &mut self.buf);
+                &self.buf
+            }
+            None => buf,
+        }
+    }
+
+    /// Record `written` bytes of `buf` written after [`FileWriterState::encrypt`]
+    pub fn advance(&mut self, buf: &[u8], written: usize) {
+        if let Some(ref mut hasher) = self.hasher {
+            let data = match self.filter {
+                Some(_) => &self.buf[..written],
+                None => &buf[..written],
+            };
+            hasher.update(data);
+        }
+
+        self.checksum.update_buffer(&buf[..written]);
+        self.pos += written as u64;
+    }
+
+    /// Add finished segment with `size` bytes of original data stored in `archive_size` bytes
+    pub fn push_segment(&mut self, compressed: bool, size: u64, archive_size: u64) {
+        self.segments.push(DataSegment {
+            compressed,
+            start: self.segment_start,
+            size,
+            archive_size,
+            next: None,
+        });
+        self.segment_start += archive_size;
+    }
+
+    /// Add file to index of `writer`.
+    /// Fails if written data does not match checksum set in advance.
+    /// Returns file index and size of written data to drop if the file is deduplicated
+    pub fn finish(self, writer: &mut WriterState) -> io::Result<(usize, Option<u64>)> {
+        let checksum = self.checksum.hash();
+        if let Some(expected) = self.expected_checksum
+            && expected != checksum
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("file checksum mismatch, expected: {expected:#X} actual: {checksum:#X}"),
+            ));
+        }
+
+        let entry = XP3FileEntry {
+            size: self.segments.iter().map(|segment| segment.size).sum(),
+            archive_size: self
+                .segments
+                .iter()
+                .map(|segment| segment.archive_size)
+                .sum(),
+            checksum,
+            ..self.entry
+        };
+
+        if let Some(ref mut dedup) = writer.dedup
+            && let Some(hasher) = self.hasher
+            && let Some(index) = dedup.find_or_insert(
+                entry.size,
+                hasher.finalize().into(),
+                writer.entries.entries.len(),
+            )
+        {
+            // Drop written data and share data of the identical file
+            let dropped = entry.archive_size;
+            dedup.add_saved(dropped);
+            return Ok((writer.entries.push_shared(entry, index), Some(dropped)));
+        }
+
+        Ok((writer.entries.push(entry, self.segments), None))
+    }
+}
+
+/// Public setters of file writer types delegating to their `file` field
+macro_rules! file_writer_setters {
+    () => {
+        /// Set file protected flag
+        pub fn protected(&mut self, protected: bool) {
+            self.file.entry.protected = protected;
+        }
+
+        /// Set file name
+        pub fn name(&mut self, name: String) {
+            self.file.entry.name = name;
+        }
+
+        /// Set file timestamp
+        pub fn timestamp(&mut self, timestamp: Option<u64>) {
+            self.file.entry.timestamp = timestamp;
+        }
+
+        /// Set hashed name written in file index.
+        /// Real name is written in `hnfn` table if set.
+        pub fn hashed_name(&mut self, hashed_name: Option<String>) {
+            self.file.entry.hashed_name = hashed_name;
+        }
+
+        /// Set unknown chunks of file index written as is
+        pub fn unknown_chunks(&mut self, chunks: Vec<$crate::XP3Chunk>) {
+            self.file.entry.unknown_chunks = chunks;
+        }
+
+        /// Set filter encrypting file data
+        pub fn filter(&mut self, filter: Option<::std::sync::Arc<dyn $crate::filter::XP3Filter>>) {
+            self.file.filter = filter;
+        }
+
+        /// Set adler32 checksum of file data known in advance.
+        /// Without it, data of files encrypted by filters keyed on the checksum is buffered in memory until finish.
+        /// Finishing file fails if written data does not match.
+        pub fn checksum(&mut self, checksum: Option<u32>) {
+            self.file.set_checksum(checksum);
+        }
+
+        /// Set compression level of segments started afterward
+        pub fn compression(&mut self, compression: Option<u8>) {
+            self.file.compression = compression;
+        }
+
+        /// Set adaptive compression of segments started afterward.
+        /// If enabled, each segment is buffered and compressed in memory,
+        /// then stored uncompressed if compression does not reduce its size.
+        /// Without segment size, segments are split every 4 MiB to bound memory use.
+        pub fn adaptive_compression(&mut self, adaptive: bool) {
+            self.file.adaptive = adaptive;
+        }
+
+        /// Set maximum size of segment data before compression.
+        /// New segment is started when current one is full, zero is treated as unlimited.
+        pub fn segment_size(&mut self, size: Option<u64>) {
+            self.file.segment_size = size.filter(|&size| size > 0);
+        }
+    };
+}
+
+pub(crate) use file_writer_setters;
@@ -0,0 +1,188 @@
+use std::io::{self, Cursor, Read, Write};
+
+use xp3::{
+    error::{XP3Problem, XP3ReadError, XP3ReadErrorKind},
+    header::XP3Version,
+    sync::{XP3Archive, XP3SharedArchive, XP3Writer},
+};
+
+mod common;
+
+use common::{chunk, content, file_segments, index_data, sample, split_chunks};
+
+/// Archive of stored `sample` with one byte of its data flipped, and intact compressed `b`
+fn corrupted() -> Vec<u8> {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    for (name, data, compression) in [("sample", sample(), None), ("b", content("b"), Some(6))] {
+        let mut file = writer.file(name.into(), false, compression).unwrap();
+        file.write_all(&data).unwrap();
+        file.finish().unwrap();
+    }
+    let mut data = writer.finish(None).unwrap().into_inner();
+
+    let segment = file_segments(&data)[0][0];
+    assert!(!segment.compressed);
+    data[segment.start as usize + 500] ^= 0xFF;
+    data
+}
+
+/// Assert `err` is checksum mismatch of the first file
+fn check_mismatch(err: XP3ReadError) {
+    assert_eq!(err.index, 0);
+    assert_eq!(err.name, "sample");
+    let XP3ReadErrorKind::ChecksumMismatch(mismatch) = err.kind else {
+        panic!("{:?} is not checksum mismatch", err.kind);
+    };
+    assert_ne!(mismatch.expected, mismatch.actual);
+}
+
+/// Assert read error is checksum mismatch of the first file
+fn check_read_mismatch(res: io::Result<usize>) {
+    let err = res.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    check_mismatch(XP3ReadError::try_from(err).unwrap());
+}
+
+/// Assert only the first file is reported by `verify_all`
+fn check_verify_all(mut corrupted: Vec<XP3ReadError>) {
+    assert_eq!(corrupted.len(), 1);
+    check_mismatch(corrupted.remove(0));
+}
+
+/// Assert data read without verification differs from the original only at the flipped byte
+fn check_unverified(buf: &[u8]) {
+    let mut expected = sample();
+    expected[500] ^= 0xFF;
+    assert_eq!(buf, expected);
+}
+
+#[test]
+fn checksum_mismatch() {
+    let mut archive = XP3Archive::open(Cursor::new(corrupted())).unwrap();
+    archive.set_verify_checksum(true);
+    let mut buf = vec![];
+    check_read_mismatch(archive.by_index(0).unwrap().unwrap().read_to_end(&mut buf));
+    check_verify_all(archive.verify_all());
+
+    archive.set_verify_checksum(false);
+    let mut buf = vec![];
+    archive
+        .by_index(0)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    check_unverified(&buf);
+}
+
+#[test]
+fn checksum_mismatch_shared() {
+    let data = corrupted();
+    let mut archive = XP3SharedArchive::open(&data[..]).unwrap();
+    archive.set_verify_checksum(true);
+    let mut buf = vec![];
+    check_read_mismatch(archive.by_index(0).unwrap().unwrap().read_to_end(&mut buf));
+    check_mismatch(archive.bytes(0).unwrap().unwrap_err());
+    check_verify_all(archive.verify_all());
+
+    archive.set_verify_checksum(false);
+    let mut buf = vec![];
+    archive
+        .by_index(0)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    check_unverified(&buf);
+    check_unverified(&archive.bytes(0).unwrap().unwrap());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn checksum_mismatch_async() {
+    use tokio::io::AsyncReadExt;
+
+    let mut archive = xp3::read::XP3Archive::open(Cursor::new(corrupted()))
+        .await
+        .unwrap();
+    archive.set_verify_checksum(true);
+    let mut buf = vec![];
+    check_read_mismatch(
+        archive
+            .by_index(0)
+            .await
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut buf)
+            .await,
+    );
+    check_verify_all(archive.verify_all().await);
+
+    archive.set_verify_checksum(false);
+    let mut buf = vec![];
+    archive
+        .by_index(0)
+        .await
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .await
+        .unwrap();
+    check_unverified(&buf);
+}
+
+/// Archive of stored `sample` with its `adlr` chunk replaced by `checksum`, removed if [`None`]
+fn with_checksum(checksum: Option<u32>) -> Vec<u8> {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    let mut file = writer.file("sample".into(), false, None).unwrap();
+    file.write_all(&sample()).unwrap();
+    file.finish().unwrap();
+    let data = writer.finish(None).unwrap().into_inner();
+
+    let (start, index) = index_data(&data);
+    let index = split_chunks(index)
+        .into_iter()
+        .flat_map(|(tag, data)| {
+            let data = split_chunks(data)
+                .into_iter()
+                .flat_map(|(tag, data)| match (&tag, checksum) {
+                    (b"adlr", Some(checksum)) => chunk(&tag, &checksum.to_le_bytes()),
+                    (b"adlr", None) => vec![],
+                    _ => chunk(&tag, data),
+                })
+                .collect::<Vec<_>>();
+            chunk(&tag, &data)
+        })
+        .collect::<Vec<_>>();
+
+    let mut archive = data[..start].to_vec();
+    archive.push(0);
+    archive.extend_from_slice(&(index.len() as u64).to_le_bytes());
+    archive.extend_from_slice(&index);
+    archive
+}
+
+#[test]
+fn zero_checksum_is_verified() {
+    let mut archive = XP3Archive::open(Cursor::new(with_checksum(Some(0)))).unwrap();
+    assert!(archive.validate().unwrap().is_empty());
+    check_verify_all(archive.verify_all());
+}
+
+#[test]
+fn missing_checksum_is_reported() {
+    let data = with_checksum(None);
+    let mut archive = XP3Archive::open(Cursor::new(data.clone())).unwrap();
+    assert_eq!(
+        archive.validate().unwrap(),
+        [XP3Problem::MissingChecksum {
+            entry: 0,
+            name: "sample".into()
+        }]
+    );
+    assert!(archive.verify_all().is_empty());
+
+    let mut archive = XP3SharedArchive::open(&data[..]).unwrap();
+    archive.set_verify_checksum(true);
+    assert_eq!(archive.bytes(0).unwrap().unwrap(), sample());
+}
@@ -0,0 +1,334 @@
+use std::{
+    env, fs,
+    io::{Cursor, Read, Write},
+    path::Path,
+    process::{Command, Output},
+};
+
+use xp3::{
+    XP3Chunk,
+    header::XP3Version,
+    sync::{XP3Archive, XP3Writer},
+};
+
+mod common;
+
+use common::test_dir;
+
+/// Run xp3 binary with `args`
+fn run(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_xp3"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Run xp3 binary with `args`, asserting it succeeds
+fn xp3(args: &[&Path]) -> Output {
+    let output = run(args);
+    assert!(
+        output.status.success(),
+        "xp3 {args:?}: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Names and contents of archive entries
+fn entries(path: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut archive = XP3Archive::open(Cursor::new(fs::read(path).unwrap())).unwrap();
+    (0..archive.entries().len())
+        .map(|index| {
+            let mut buf = vec![];
+            archive
+                .by_index(index)
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            (archive.entries()[index].name.clone(), buf)
+        })
+        .collect()
+}
+
+/// Files left in `dir`
+fn files(dir: &Path) -> Vec<String> {
+    let mut files = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort_unstable();
+    files
+}
+
+#[test]
+fn pack_extract_round_trip() {
+    let dir = test_dir("round-trip");
+    let input = dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::write(input.join("a.txt"), "a".repeat(1000)).unwrap();
+    fs::write(input.join("sub/b.bin"), [1, 2, 3]).unwrap();
+
+    let archive = dir.join("data.xp3");
+    xp3(&[
+        "pack".as_ref(),
+        &input,
+        &archive,
+        "--compression".as_ref(),
+        "9".as_ref(),
+    ]);
+    let output = dir.join("output");
+    xp3(&["extract".as_ref(), &archive, &output]);
+
+    assert_eq!(
+        fs::read(output.join("a.txt")).unwrap(),
+        "a".repeat(1000).as_bytes()
+    );
+    assert_eq!(fs::read(output.join("sub/b.bin")).unwrap(), [1, 2, 3]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn list_json() {
+    let dir = test_dir("list");
+    let archive = dir.join("data.xp3");
+    let mut writer = XP3Writer::new(
+        XP3Version::Current { minor: 1 },
+        fs::File::create(&archive).unwrap(),
+    )
+    .unwrap();
+    let mut file = writer
+        .file("dir/\"quoted\".txt".into(), true, None)
+        .unwrap();
+    file.timestamp(Some(42));
+    file.write_all(b"abc").unwrap();
+    file.finish().unwrap();
+    writer.finish(None).unwrap();
+
+    let output = xp3(&["list".as_ref(), &archive, "--json".as_ref()]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim_end(),
+        "[{\"name\":\"dir/\\\"quoted\\\".txt\",\"hashed_name\":null,\"size\":3,\"archive_size\":3,\
+         \"checksum\":38600999,\"protected\":true,\"timestamp\":42}]"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn add_remove_rename() {
+    let dir = test_dir("edit");
+    let archive = dir.join("data.xp3");
+    let mut writer = XP3Writer::new(
+        XP3Version::Current { minor: 1 },
+        fs::File::create(&archive).unwrap(),
+    )
+    .unwrap();
+    for (name, data) in [("dup", "1"), ("keep", "2"), ("dup", "3")] {
+        let mut file = writer.file(name.into(), false, Some(6)).unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        file.finish().unwrap();
+    }
+    writer.finish(None).unwrap();
+    // File named like a fixed temporary file is left alone
+    fs::write(dir.join("data.xp3.tmp"), "user file").unwrap();
+
+    xp3(&["remove".as_ref(), &archive, "dup".as_ref()]);
+    assert_eq!(entries(&archive), [("keep".into(), b"2".to_vec())]);
+
+    let added = dir.join("added.txt");
+    fs::write(&added, "added").unwrap();
+    xp3(&[
+        "add".as_ref(),
+        &archive,
+        &added,
+        "--name".as_ref(),
+        "new".as_ref(),
+    ]);
+    xp3(&[
+        "rename".as_ref(),
+        &archive,
+        "new".as_ref(),
+        "renamed".as_ref(),
+    ]);
+    assert_eq!(
+        entries(&archive),
+        [
+            ("keep".into(), b"2".to_vec()),
+            ("renamed".into(), b"added".to_vec())
+        ]
+    );
+
+    let output = run(&["remove".as_ref(), &archive, "missing".as_ref()]);
+    assert_eq!(output.status.code(), Some(1));
+
+    assert_eq!(files(&dir), ["added.txt", "data.xp3", "data.xp3.tmp"]);
+    assert_eq!(fs::read(dir.join("data.xp3.tmp")).unwrap(), b"user file");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Archive at `path` with a file `a.txt` of `data` and an unknown chunk
+fn write_archive(path: &Path, data: &[u8]) {
+    let mut writer = XP3Writer::new(
+        XP3Version::Current { minor: 1 },
+        fs::File::create(path).unwrap(),
+    )
+    .unwrap();
+    writer.push_chunk(XP3Chunk {
+        tag: u32::from_le_bytes(*b"zzzz"),
+        data: vec![1, 2],
+    });
+    let mut file = writer.file("a.txt".into(), false, None).unwrap();
+    file.write_all(data).unwrap();
+    file.finish().unwrap();
+    writer.finish(None).unwrap();
+}
+
+#[test]
+fn info() {
+    let dir = test_dir("info");
+    let archive = dir.join("data.xp3");
+    write_archive(&archive, b"abc");
+
+    let output = xp3(&["info".as_ref(), &archive]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "version: current:1\nfiles: 1\nsize: 3\narchive size: 3\nunknown chunks: 1\n"
+    );
+
+    let output = xp3(&["info".as_ref(), &archive, "--json".as_ref()]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim_end(),
+        "{\"version\":\"current:1\",\"files\":1,\"size\":3,\"archive_size\":3,\"unknown_chunks\":1}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn verify() {
+    let dir = test_dir("verify");
+    let archive = dir.join("data.xp3");
+    write_archive(&archive, b"abc");
+
+    let output = xp3(&["verify".as_ref(), &archive]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "ok: 1 files\n");
+
+    let mut data = fs::read(&archive).unwrap();
+    let pos = data.windows(3).position(|window| window == b"abc").unwrap();
+    data[pos] = b'x';
+    fs::write(&archive, data).unwrap();
+
+    let output = run(&["verify".as_ref(), &archive]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .ends_with("corrupted: 1 of 1 files\n")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn add_keeps_unknown_chunks() {
+    let dir = test_dir("add");
+    let archive = dir.join("data.xp3");
+    write_archive(&archive, b"abc");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/b.txt"), "b").unwrap();
+    fs::write(dir.join("c.txt"), "c").unwrap();
+
+    // Relative paths are named relative to the current directory, absolute paths outside of it by file name
+    let output = Command::new(env!("CARGO_BIN_EXE_xp3"))
+        .current_dir(&dir)
+        .args(["add".as_ref(), archive.as_os_str(), "sub/b.txt".as_ref()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    xp3(&["add".as_ref(), &archive, &dir.join("c.txt")]);
+
+    assert_eq!(
+        entries(&archive),
+        [
+            ("a.txt".into(), b"abc".to_vec()),
+            ("sub/b.txt".into(), b"b".to_vec()),
+            ("c.txt".into(), b"c".to_vec()),
+        ]
+    );
+    let opened = XP3Archive::open(Cursor::new(fs::read(&archive).unwrap())).unwrap();
+    assert_eq!(opened.unknown_chunks()[0].tag, u32::from_le_bytes(*b"zzzz"));
+
+    let output = run(&["add".as_ref(), &archive, "../c.txt".as_ref()]);
+    assert_eq!(output.status.code(), Some(1));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unsafe_new_names() {
+    let dir = test_dir("unsafe");
+    let archive = dir.join("data.xp3");
+    write_archive(&archive, b"abc");
+    let added = dir.join("added.txt");
+    fs::write(&added, "added").unwrap();
+
+    for name in ["../evil", "/abs", "C:\\win", "a|b", "con.txt", "."] {
+        let output = run(&["rename".as_ref(), &archive, "a.txt".as_ref(), name.as_ref()]);
+        assert_eq!(output.status.code(), Some(1), "{name}");
+        assert!(
+            String::from_utf8(output.stderr)
+                .unwrap()
+                .contains("unsafe entry name")
+        );
+
+        let output = run(&[
+            "add".as_ref(),
+            &archive,
+            &added,
+            "--name".as_ref(),
+            name.as_ref(),
+        ]);
+        assert_eq!(output.status.code(), Some(1), "{name}");
+    }
+    assert_eq!(entries(&archive), [("a.txt".into(), b"abc".to_vec())]);
+
+    xp3(&[
+        "rename".as_ref(),
+        &archive,
+        "a.txt".as_ref(),
+        "../evil".as_ref(),
+        "--force".as_ref(),
+    ]);
+    assert_eq!(entries(&archive), [("../evil".into(), b"abc".to_vec())]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn usage_errors() {
+    let dir = test_dir("usage");
+    let archive = dir.join("data.xp3");
+    write_archive(&archive, b"abc");
+
+    for args in [
+        &["list".as_ref(), &*archive, "--jsn".as_ref()][..],
+        &["info".as_ref(), &archive, "extra".as_ref()],
+        &["list".as_ref(), &archive, "--json=1".as_ref()],
+        &["rename".as_ref(), &archive, "a.txt".as_ref()],
+        &["remove".as_ref(), &archive],
+        &["extract".as_ref(), &archive, "--include".as_ref()],
+        &["unknown".as_ref()],
+        &[],
+    ] {
+        let output = run(args);
+        assert_eq!(output.status.code(), Some(2), "xp3 {args:?}");
+        assert!(
+            String::from_utf8(output.stderr)
+                .unwrap()
+                .contains("Usage: xp3")
+        );
+    }
+
+    // Archive is left untouched
+    assert_eq!(entries(&archive), [("a.txt".into(), b"abc".to_vec())]);
+
+    let output = run(&["list".as_ref(), &dir.join("missing.xp3")]);
+    assert_eq!(output.status.code(), Some(1));
+    fs::remove_dir_all(&dir).unwrap();
+}
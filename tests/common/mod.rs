@@ -0,0 +1,172 @@
+//! Fixtures shared by integration tests
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::{Cursor, Read, Write},
+    path::PathBuf,
+    process,
+};
+
+use xp3::{
+    error::XP3OpenError,
+    header::XP3Version,
+    limits::{XP3LimitKind, XP3Limits},
+    options::XP3ArchiveOptions,
+    sync::{XP3Archive, XP3Writer},
+};
+
+/// Data of 1000 bytes without short repeats
+pub fn sample() -> Vec<u8> {
+    (0..1000_u32).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+/// Data of `size` bytes unlikely to compress
+pub fn noise(seed: u32, size: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..size)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+/// Compressible content of file `name`
+pub fn content(name: &str) -> Vec<u8> {
+    name.repeat(200).into_bytes()
+}
+
+/// Archive with compressed files named by `names` containing their [`content`]
+pub fn archive(names: &[&str]) -> Vec<u8> {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    for name in names {
+        let mut file = writer.file(name.to_string(), false, Some(6)).unwrap();
+        file.write_all(&content(name)).unwrap();
+        file.finish().unwrap();
+    }
+    writer.finish(Some(6)).unwrap().into_inner()
+}
+
+/// Data of archive written into `stream`, truncated at the end of archive where finish left it
+pub fn finished(stream: Cursor<Vec<u8>>) -> Vec<u8> {
+    let end = stream.position() as usize;
+    let mut data = stream.into_inner();
+    data.truncate(end);
+    data
+}
+
+/// Read whole file at `index`
+pub fn read_all(archive: &mut XP3Archive<Cursor<Vec<u8>>>, index: usize) -> Vec<u8> {
+    let mut buf = vec![];
+    archive
+        .by_index(index)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    buf
+}
+
+/// Options with `limits`
+pub fn with_limits(limits: XP3Limits) -> XP3ArchiveOptions {
+    let mut options = XP3ArchiveOptions::new();
+    options.limits(limits);
+    options
+}
+
+/// Assert `err` is exceeding limit of `kind`
+pub fn assert_limit_exceeded(err: &XP3OpenError, kind: XP3LimitKind) {
+    assert!(
+        matches!(err, XP3OpenError::LimitExceeded { kind: exceeded, .. } if *exceeded == kind),
+        "{err:?} is not exceeding {kind:?}"
+    );
+}
+
+/// Split chunks in `data` into tag and data
+pub fn split_chunks(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = vec![];
+    while !data.is_empty() {
+        let size = u64::from_le_bytes(data[4..12].try_into().unwrap()) as usize;
+        chunks.push((data[..4].try_into().unwrap(), &data[12..12 + size]));
+        data = &data[12 + size..];
+    }
+    chunks
+}
+
+/// Chunk with `tag` and `data`
+pub fn chunk(tag: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut buf = tag.to_vec();
+    buf.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+/// Uncompressed index data of archive and its start
+pub fn index_data(data: &[u8]) -> (usize, &[u8]) {
+    // Index offset is at the end of header, followed by an empty index in an empty archive
+    let header_len = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![]))
+        .unwrap()
+        .finish(None)
+        .unwrap()
+        .into_inner()
+        .len()
+        - 9;
+    let offset = &data[header_len - 8..header_len];
+    let start = u64::from_le_bytes(offset.try_into().unwrap()) as usize;
+    assert_eq!(data[start], 0);
+    (start, &data[start + 9..])
+}
+
+/// Segment of a file as stored in index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub compressed: bool,
+    pub start: u64,
+    pub size: u64,
+    pub archive_size: u64,
+}
+
+impl Segment {
+    /// Stored data of segment in archive `data`
+    pub fn data<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.start as usize..(self.start + self.archive_size) as usize]
+    }
+}
+
+/// Segments of every file in uncompressed index of archive
+pub fn file_segments(data: &[u8]) -> Vec<Vec<Segment>> {
+    let read_u64 = |data: &[u8], offset: usize| {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    };
+
+    split_chunks(index_data(data).1)
+        .into_iter()
+        .filter(|(tag, _)| tag == b"File")
+        .map(|(_, file)| {
+            split_chunks(file)
+                .into_iter()
+                .filter(|(tag, _)| tag == b"segm")
+                .flat_map(|(_, segments)| segments.chunks(28))
+                .map(|segment| Segment {
+                    compressed: segment[..4] != [0; 4],
+                    start: read_u64(segment, 4),
+                    size: read_u64(segment, 12),
+                    archive_size: read_u64(segment, 20),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Empty directory for test `name` of the current test binary
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "xp3-{}-{name}-{}",
+        env!("CARGO_CRATE_NAME"),
+        process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
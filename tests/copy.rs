@@ -0,0 +1,130 @@
+use std::{
+    io::{Cursor, Read, Write},
+    sync::Arc,
+};
+
+use xp3::{
+    XP3FileEntry, crypt,
+    filter::XP3Filter,
+    header::XP3Version,
+    sync::{XP3Archive, XP3Writer},
+};
+
+mod common;
+
+use common::{content, file_segments, finished, sample};
+
+fn filter() -> Arc<dyn XP3Filter> {
+    crypt::by_name("xor", &[0x5A]).unwrap()
+}
+
+/// Encrypted archive of compressed, stored and deduplicated files, in segments of 300 bytes
+fn source() -> Vec<u8> {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    writer.set_filter(Some(filter()));
+    writer.set_segment_size(Some(300));
+    writer.set_deduplicate(true);
+    for (name, data, protected, compression) in [
+        ("compressed", sample(), true, Some(9)),
+        ("stored", content("stored"), false, None),
+        ("deduplicated", sample(), false, Some(9)),
+    ] {
+        let mut file = writer.file(name.into(), protected, compression).unwrap();
+        file.timestamp(Some(name.len() as u64));
+        file.write_all(&data).unwrap();
+        file.finish().unwrap();
+    }
+    assert!(writer.deduplicated_size() > 0);
+    finished(writer.finish(None).unwrap())
+}
+
+/// Metadata kept by raw copy
+fn metadata(entry: &XP3FileEntry) -> (&str, bool, u64, u64, u32, Option<u64>) {
+    (
+        &entry.name,
+        entry.protected,
+        entry.size,
+        entry.archive_size,
+        entry.checksum,
+        entry.timestamp,
+    )
+}
+
+/// Assert `copy` has the files of `source` with identical metadata and segment data
+fn check_copy(source: &[u8], copy: &[u8]) {
+    let source_archive = XP3Archive::open(Cursor::new(source)).unwrap();
+    let mut archive = XP3Archive::open(Cursor::new(copy)).unwrap();
+    assert_eq!(
+        archive.entries().iter().map(metadata).collect::<Vec<_>>(),
+        source_archive
+            .entries()
+            .iter()
+            .map(metadata)
+            .collect::<Vec<_>>()
+    );
+
+    let source_segments = file_segments(source);
+    let segments = file_segments(copy);
+    assert_eq!(segments.len(), 3);
+    for (segments, source_segments) in segments.iter().zip(&source_segments) {
+        assert_eq!(segments.len(), source_segments.len());
+        for (segment, source_segment) in segments.iter().zip(source_segments) {
+            assert_eq!(segment.compressed, source_segment.compressed);
+            assert_eq!(segment.size, source_segment.size);
+            assert_eq!(segment.data(copy), source_segment.data(source));
+        }
+    }
+    assert!(segments[0].iter().all(|segment| segment.compressed));
+    assert!(segments[1].iter().all(|segment| !segment.compressed));
+
+    archive.set_filter(Some(filter()));
+    archive.set_verify_checksum(true);
+    for (index, expected) in [sample(), content("stored"), sample()].iter().enumerate() {
+        let mut buf = vec![];
+        archive
+            .by_index(index)
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, *expected);
+    }
+}
+
+#[test]
+fn copy_raw_round_trip() {
+    let source = source();
+    let mut archive = XP3Archive::open(Cursor::new(&source[..])).unwrap();
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    for index in 0..3 {
+        assert_eq!(
+            writer.copy_raw(&mut archive, index).unwrap().unwrap(),
+            index
+        );
+    }
+    assert!(writer.copy_raw(&mut archive, 3).is_none());
+
+    check_copy(&source, &writer.finish(None).unwrap().into_inner());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn copy_raw_round_trip_async() {
+    let source = source();
+    let mut archive = xp3::read::XP3Archive::open(Cursor::new(&source[..]))
+        .await
+        .unwrap();
+    let mut writer =
+        xp3::write::XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![]))
+            .await
+            .unwrap();
+    for index in 0..3 {
+        assert_eq!(
+            writer.copy_raw(&mut archive, index).await.unwrap().unwrap(),
+            index
+        );
+    }
+    assert!(writer.copy_raw(&mut archive, 3).await.is_none());
+
+    check_copy(&source, &writer.finish(None).await.unwrap().into_inner());
+}
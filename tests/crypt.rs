@@ -0,0 +1,233 @@
+use std::{
+    env, fs,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    process,
+    sync::Arc,
+};
+
+use xp3::{
+    crypt,
+    filter::XP3Filter,
+    header::XP3Version,
+    sync::{
+        XP3Archive, XP3SharedArchive, XP3Writer,
+        fs::{PackOptions, pack_dir},
+    },
+};
+
+fn sample() -> Vec<u8> {
+    (0..70000_u32).map(|i| (i * 31 % 253) as u8).collect()
+}
+
+fn filters() -> Vec<Arc<dyn XP3Filter>> {
+    vec![
+        crypt::by_name("xor", &[0x5A]).unwrap(),
+        crypt::by_name("checksum-xor", &[]).unwrap(),
+        crypt::by_name("key-xor", b"kirikiri").unwrap(),
+        crypt::by_name("table-xor", &(0..=255).rev().collect::<Vec<u8>>()).unwrap(),
+    ]
+}
+
+fn write_archive(filter: &Arc<dyn XP3Filter>, data: &[u8]) -> Vec<u8> {
+    write_archive_with(filter, data, true)
+}
+
+/// Write stored and compressed file, setting checksum in advance if `checksum` is true
+fn write_archive_with(filter: &Arc<dyn XP3Filter>, data: &[u8], checksum: bool) -> Vec<u8> {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    writer.set_filter(Some(filter.clone()));
+    writer.set_segment_size(Some(30000));
+    for (name, compression) in [("stored.bin", None), ("compressed.bin", Some(6))] {
+        let mut file = writer.file(name.into(), true, compression).unwrap();
+        if checksum {
+            file.checksum(Some(adler32::adler32(data).unwrap()));
+        }
+        for chunk in data.chunks(4096) {
+            file.write_all(chunk).unwrap();
+        }
+        file.finish().unwrap();
+    }
+
+    writer.finish(Some(6)).unwrap().into_inner()
+}
+
+/// Read every file with `filter` and compare with `data`
+fn check_archive(archive: Vec<u8>, filter: &Arc<dyn XP3Filter>, data: &[&[u8]]) {
+    let mut shared = XP3SharedArchive::open(&archive[..]).unwrap();
+    shared.set_filter(Some(filter.clone()));
+    shared.set_verify_checksum(true);
+    for (index, data) in data.iter().enumerate() {
+        assert_eq!(shared.bytes(index).unwrap().unwrap(), *data, "{filter:?}");
+    }
+
+    let mut archive = XP3Archive::open(Cursor::new(archive)).unwrap();
+    archive.set_filter(Some(filter.clone()));
+    assert!(archive.verify_all().is_empty(), "{filter:?}");
+    for (index, data) in data.iter().enumerate() {
+        let mut buf = vec![];
+        archive
+            .by_index(index)
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, *data, "{filter:?}");
+    }
+}
+
+#[test]
+fn by_name() {
+    for name in crypt::SCHEMES {
+        assert!(
+            crypt::by_name(name, &[1]).is_some() || crypt::by_name(name, &[]).is_some(),
+            "{name}"
+        );
+    }
+
+    assert!(crypt::by_name("unknown", &[]).is_none());
+    assert!(crypt::by_name("xor", &[1, 2]).is_none());
+    assert!(crypt::by_name("key-xor", &[]).is_none());
+    assert!(crypt::by_name("table-xor", &[]).is_none());
+}
+
+#[test]
+fn empty_key_is_rejected() {
+    assert!(crypt::KeyXor::new(vec![]).is_none());
+    assert!(crypt::TableXor::new(vec![]).is_none());
+
+    let filter = crypt::KeyXor::new(vec![1, 2]).unwrap();
+    assert_eq!(filter.key(), [1, 2]);
+    let mut buf = [0; 3];
+    filter.decrypt(&Default::default(), 1, &mut buf);
+    assert_eq!(buf, [2, 1, 2]);
+}
+
+#[test]
+fn round_trip() {
+    let data = sample();
+    for filter in filters() {
+        let mut archive = XP3Archive::open(Cursor::new(write_archive(&filter, &data))).unwrap();
+        for index in 0..2 {
+            let mut buf = vec![];
+            archive
+                .by_index(index)
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            assert_ne!(buf, data, "{filter:?} did not encrypt");
+        }
+
+        archive.set_filter(Some(filter.clone()));
+        for index in 0..2 {
+            let mut buf = vec![];
+            archive
+                .by_index(index)
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            assert_eq!(buf, data, "{filter:?}");
+        }
+    }
+}
+
+#[test]
+fn round_trip_without_checksum() {
+    let data = sample();
+    for filter in filters() {
+        let archive = write_archive_with(&filter, &data, false);
+        check_archive(archive, &filter, &[&data, &data]);
+    }
+}
+
+#[test]
+fn pack_dir_round_trip() {
+    let dir = env::temp_dir().join(format!("xp3-crypt-{}", process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    let data = sample();
+    fs::write(dir.join("a.bin"), &data).unwrap();
+    fs::write(dir.join("sub/b.txt"), b"hello world").unwrap();
+
+    for filter in filters() {
+        let mut writer =
+            XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+        writer.set_filter(Some(filter.clone()));
+        let options = PackOptions {
+            compression: Some(6),
+            ..Default::default()
+        };
+        pack_dir(&mut writer, &dir, &options).unwrap();
+        let archive = writer.finish(None).unwrap().into_inner();
+        check_archive(archive, &filter, &[&data, b"hello world"]);
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn round_trip_seek() {
+    let data = sample();
+    for filter in filters() {
+        let mut archive = XP3Archive::open(Cursor::new(write_archive(&filter, &data))).unwrap();
+        archive.set_filter(Some(filter.clone()));
+        for index in 0..2 {
+            let mut file = archive.by_index(index).unwrap().unwrap();
+            for offset in [12345, 7, 69990] {
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                let mut buf = [0; 10];
+                file.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, data[offset as usize..][..10], "{filter:?}");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn write_without_checksum_async() {
+    use tokio::io::AsyncWriteExt;
+    use xp3::write::XP3Writer;
+
+    let data = sample();
+    for filter in filters() {
+        let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![]))
+            .await
+            .unwrap();
+        writer.set_filter(Some(filter.clone()));
+        for (name, compression) in [("stored.bin", None), ("compressed.bin", Some(6))] {
+            let mut file = writer.file(name.into(), true, compression).await.unwrap();
+            file.write_all(&data).await.unwrap();
+            file.finish().await.unwrap();
+        }
+        let archive = writer.finish(None).await.unwrap().into_inner();
+        check_archive(archive, &filter, &[&data, &data]);
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn round_trip_async() {
+    use tokio::io::AsyncReadExt;
+    use xp3::read::XP3Archive;
+
+    let data = sample();
+    for filter in filters() {
+        let mut archive = XP3Archive::open(Cursor::new(write_archive(&filter, &data)))
+            .await
+            .unwrap();
+        archive.set_filter(Some(filter.clone()));
+        for index in 0..2 {
+            let mut buf = vec![];
+            archive
+                .by_index(index)
+                .await
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut buf)
+                .await
+                .unwrap();
+            assert_eq!(buf, data, "{filter:?}");
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{Cursor, Read, Write};
+
+use xp3::{
+    header::XP3Version,
+    limits::{XP3LimitKind, XP3Limits},
+    sync::{XP3Archive, XP3Editor, XP3Writer},
+};
+
+mod common;
+
+use common::{archive, assert_limit_exceeded, finished, noise, with_limits};
+
+#[test]
+fn edit_with_options() {
+    let options = with_limits(XP3Limits {
+        max_name_len: 3,
+        ..XP3Limits::DEFAULT
+    });
+    let err = options
+        .edit(Cursor::new(archive(&["long name"])))
+        .unwrap_err();
+    assert_limit_exceeded(&err, XP3LimitKind::NameLength);
+
+    let editor = options.edit(Cursor::new(archive(&["abc"]))).unwrap();
+    assert_eq!(editor.entries()[0].name, "abc");
+}
+
+/// Archive with deduplicated files of `files` names and contents
+fn archive_with(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    writer.set_deduplicate(true);
+    writer.set_segment_size(Some(1000));
+    for (name, data) in files {
+        let mut file = writer.file(name.to_string(), false, Some(6)).unwrap();
+        file.write_all(data).unwrap();
+        file.finish().unwrap();
+    }
+    finished(writer.finish(Some(6)).unwrap())
+}
+
+/// Names and contents of every file, checking index consistency
+fn contents(data: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+    let mut archive = XP3Archive::open(Cursor::new(data)).unwrap();
+    assert!(archive.validate().unwrap().is_empty());
+    (0..archive.entries().len())
+        .map(|index| {
+            let name = archive.entries()[index].name.clone();
+            let mut buf = vec![];
+            archive
+                .by_index(index)
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            (name, buf)
+        })
+        .collect()
+}
+
+#[test]
+fn edit_then_commit() {
+    let data = archive_with(&[("a", b"a"), ("b", b"b"), ("c", b"c"), ("d", b"d")]);
+    let mut editor = XP3Editor::open(Cursor::new(data)).unwrap();
+    assert!(editor.remove(1));
+    assert!(editor.replace(2, b"new c".to_vec(), Some(9)));
+    assert!(editor.rename(3, "e".into()));
+    assert!(!editor.remove(4));
+    assert!(editor.is_removed(1));
+
+    let data = editor.commit(None).unwrap().into_inner();
+    assert_eq!(
+        contents(data),
+        [
+            ("a".into(), b"a".to_vec()),
+            ("c".into(), b"new c".to_vec()),
+            ("e".into(), b"d".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn compact_then_commit() {
+    let (a, b, c) = (noise(1, 3000), noise(2, 2500), noise(3, 1500));
+    let original = archive_with(&[("a", &a), ("b", &b), ("shared", &a), ("c", &c)]);
+
+    let mut editor = XP3Editor::open(Cursor::new(original.clone())).unwrap();
+    let removed = editor.entries()[0].archive_size;
+    editor.remove(0);
+    editor.replace(1, b"small".to_vec(), None);
+    editor.rename(3, "renamed".into());
+
+    // Data of removed `a` stays used by `shared`, only replaced `b` is reclaimed
+    let reclaimed = editor.compact().unwrap();
+    assert!(reclaimed > 0 && reclaimed < removed + editor.entries()[1].archive_size);
+
+    let stream = editor.commit(Some(6)).unwrap();
+    let end = stream.position() as usize;
+    let mut data = stream.into_inner();
+    data.truncate(end);
+    assert!(data.len() < original.len());
+
+    assert_eq!(
+        contents(data),
+        [
+            ("b".into(), b"small".to_vec()),
+            ("shared".into(), a),
+            ("renamed".into(), c),
+        ]
+    );
+}
+
+#[test]
+fn write_to_matches_commit() {
+    let (a, b) = (noise(1, 3000), noise(2, 2500));
+    let original = archive_with(&[("a", &a), ("b", &b), ("a copy", &a)]);
+
+    let edit = |editor: &mut XP3Editor<Cursor<Vec<u8>>>| {
+        editor.remove(1);
+        editor.rename(2, "copy".into());
+    };
+
+    let mut editor = XP3Editor::open(Cursor::new(original.clone())).unwrap();
+    edit(&mut editor);
+    let written = editor
+        .write_to(Cursor::new(vec![]), Some(6))
+        .unwrap()
+        .into_inner();
+    assert!(written.len() < original.len() - b.len() / 2);
+    // `a` and its copy share data
+    assert!(written.len() < a.len() + b.len() / 2);
+
+    let mut editor = XP3Editor::open(Cursor::new(original)).unwrap();
+    edit(&mut editor);
+    let committed = editor.commit(Some(6)).unwrap().into_inner();
+
+    let expected = [("a".into(), a.clone()), ("copy".into(), a)];
+    assert_eq!(contents(written), expected);
+    assert_eq!(contents(committed), expected);
+}
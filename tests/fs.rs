@@ -0,0 +1,233 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use xp3::{
+    name::NameFix,
+    sync::{
+        XP3Archive,
+        fs::{ExtractOptions, ExtractReport, extract_all, from_timestamp, to_timestamp},
+    },
+};
+
+mod common;
+
+use common::{archive, content, test_dir};
+
+/// Paths of files under `dir` relative to it joined with `/`, sorted
+fn extracted(dir: &Path) -> Vec<String> {
+    fn collect(dir: &Path, prefix: &str, files: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            let name = prefix.to_string() + entry.file_name().to_str().unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                collect(&entry.path(), &(name + "/"), files);
+            } else {
+                files.push(name);
+            }
+        }
+    }
+
+    let mut files = vec![];
+    collect(dir, "", &mut files);
+    files.sort_unstable();
+    files
+}
+
+/// Extract archive of files named by `names` into directory for test `name` with `options`.
+/// Returns report and extracted files
+fn extract(name: &str, names: &[&str], options: &ExtractOptions) -> (ExtractReport, Vec<String>) {
+    // Extractions of tests running in parallel use separate directories
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = test_dir(&format!("{name}-{}", COUNT.fetch_add(1, Ordering::Relaxed)));
+    let mut archive = XP3Archive::open(Cursor::new(archive(names))).unwrap();
+    let report = extract_all(&mut archive, &dir, options).unwrap();
+    let files = extracted(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    (report, files)
+}
+
+/// Names extracted with `include` and `exclude` patterns from an archive of `names`
+fn filtered(names: &[&str], include: &[&str], exclude: &[&str]) -> Vec<String> {
+    let options = ExtractOptions {
+        include: include.iter().map(|pattern| pattern.to_string()).collect(),
+        exclude: exclude.iter().map(|pattern| pattern.to_string()).collect(),
+        ..Default::default()
+    };
+    extract("filter", names, &options).1
+}
+
+#[test]
+fn timestamp_round_trip() {
+    let time = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_700);
+    assert_eq!(from_timestamp(to_timestamp(time)), time);
+
+    let time = UNIX_EPOCH - Duration::from_secs(86400);
+    assert_eq!(from_timestamp(to_timestamp(time)), time);
+}
+
+#[test]
+fn timestamp_saturates() {
+    let far = UNIX_EPOCH.checked_add(Duration::from_secs(u64::MAX / 4));
+    if let Some(far) = far {
+        assert_eq!(to_timestamp(far), u64::MAX);
+    }
+
+    let early = UNIX_EPOCH.checked_sub(Duration::from_secs(u64::MAX / 4));
+    if let Some(early) = early {
+        assert_eq!(to_timestamp(early), 0);
+    }
+
+    assert!(to_timestamp(SystemTime::now()) > 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn pack_dir_skips_symlink_loop() {
+    use std::{io::Read, os::unix::fs::symlink};
+
+    use xp3::{
+        header::XP3Version,
+        sync::{
+            XP3Writer,
+            fs::{PackOptions, pack_dir},
+        },
+    };
+
+    let dir = test_dir("symlink-loop");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/a.txt"), b"a").unwrap();
+    symlink(&dir, dir.join("sub/loop")).unwrap();
+    symlink(dir.join("sub/a.txt"), dir.join("b.txt")).unwrap();
+
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    pack_dir(&mut writer, &dir, &PackOptions::default()).unwrap();
+    let data = writer.finish(None).unwrap().into_inner();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let mut archive = XP3Archive::open(Cursor::new(data)).unwrap();
+    let names = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.name.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["b.txt", "sub/a.txt"]);
+
+    let mut buf = vec![];
+    archive
+        .by_index(0)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"a");
+}
+
+#[test]
+fn glob_patterns() {
+    const NAMES: [&str; 6] = [
+        "a.txt",
+        "b.png",
+        "dir/c.txt",
+        "dir/sub/d.txt",
+        "ab",
+        "dir/e",
+    ];
+
+    for (pattern, expected) in [
+        ("*.txt", &["a.txt"][..]),
+        ("dir/*", &["dir/c.txt", "dir/e"]),
+        ("dir/**", &["dir/c.txt", "dir/e", "dir/sub/d.txt"]),
+        ("**/*.txt", &["a.txt", "dir/c.txt", "dir/sub/d.txt"]),
+        ("**.txt", &["a.txt", "dir/c.txt", "dir/sub/d.txt"]),
+        ("dir/**/d.txt", &["dir/sub/d.txt"]),
+        ("?.png", &["b.png"]),
+        ("a?", &["ab"]),
+        ("dir?e", &[]),
+        ("*", &["a.txt", "ab", "b.png"]),
+        ("ab", &["ab"]),
+        ("a", &[]),
+    ] {
+        assert_eq!(filtered(&NAMES, &[pattern], &[]), expected, "{pattern}");
+    }
+}
+
+#[test]
+fn extract_include_exclude() {
+    let names = ["a.txt", "b.png", "dir/c.txt", "dir\\d.png"];
+    assert_eq!(
+        filtered(&names, &[], &[]),
+        ["a.txt", "b.png", "dir/c.txt", "dir/d.png"]
+    );
+    // Backslashes are matched as `/`
+    assert_eq!(
+        filtered(&names, &["dir/*"], &[]),
+        ["dir/c.txt", "dir/d.png"]
+    );
+    assert_eq!(
+        filtered(&names, &["**.png", "a.*"], &[]),
+        ["a.txt", "b.png", "dir/d.png"]
+    );
+    assert_eq!(
+        filtered(&names, &[], &["*.png"]),
+        ["a.txt", "dir/c.txt", "dir/d.png"]
+    );
+    // Exclude wins over include
+    assert_eq!(filtered(&names, &["dir/**"], &["**.png"]), ["dir/c.txt"]);
+}
+
+#[test]
+fn extract_rejects_unsafe_names() {
+    let names = ["../escape.txt", "/abs/b.txt", "ok.txt", ".."];
+    let (report, files) = extract("rejected", &names, &ExtractOptions::default());
+    assert_eq!(files, ["ok.txt"]);
+    assert_eq!(
+        report.rejected,
+        [
+            (0, vec![NameFix::ParentDir]),
+            (1, vec![NameFix::Root]),
+            (3, vec![NameFix::ParentDir]),
+        ]
+    );
+    assert!(report.rewritten.is_empty());
+
+    let options = ExtractOptions {
+        rewrite_names: true,
+        ..Default::default()
+    };
+    let (report, files) = extract("rewritten", &names, &options);
+    assert_eq!(files, ["abs/b.txt", "escape.txt", "ok.txt"]);
+    // Nothing is left of `..`
+    assert_eq!(report.rejected, [(3, vec![NameFix::ParentDir])]);
+    assert_eq!(
+        report
+            .rewritten
+            .iter()
+            .map(|(index, _, fixes)| (*index, fixes.clone()))
+            .collect::<Vec<_>>(),
+        [(0, vec![NameFix::ParentDir]), (1, vec![NameFix::Root])]
+    );
+}
+
+#[test]
+fn extract_skips_same_path() {
+    let dir = test_dir("same-path");
+    let names = ["a/b", "a\\b", "../a/b", "a/b", "c", "A/B"];
+    let mut archive = XP3Archive::open(Cursor::new(archive(&names))).unwrap();
+    let options = ExtractOptions {
+        rewrite_names: true,
+        ..Default::default()
+    };
+    let report = extract_all(&mut archive, &dir, &options).unwrap();
+
+    // Paths differing only in case are the same on case-insensitive file systems
+    assert_eq!(report.duplicated, [(1, 0), (2, 0), (3, 0), (5, 0)]);
+    assert_eq!(extracted(&dir), ["a/b", "c"]);
+    // First entry is kept, like lookups of duplicated names
+    assert_eq!(fs::read(dir.join("a/b")).unwrap(), content("a/b"));
+    fs::remove_dir_all(&dir).unwrap();
+}
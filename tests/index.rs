@@ -0,0 +1,499 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{Cursor, ErrorKind, Read, Write},
+    process,
+};
+
+use xp3::{
+    error::{IndexLocation, XP3OpenError, XP3Problem},
+    header::XP3Version,
+    options::XP3ArchiveOptions,
+    sync::{XP3Archive, XP3Editor, XP3SharedArchive, XP3Writer},
+};
+
+mod common;
+
+use common::{chunk, index_data, split_chunks};
+
+fn writer() -> XP3Writer<Cursor<Vec<u8>>> {
+    XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap()
+}
+
+/// Add file with `data`, hashed with `hashed_name` if set
+fn add(
+    writer: &mut XP3Writer<Cursor<Vec<u8>>>,
+    name: &str,
+    hashed_name: Option<&str>,
+    data: &[u8],
+) {
+    let mut file = writer.file(name.into(), false, Some(6)).unwrap();
+    file.hashed_name(hashed_name.map(Into::into));
+    file.write_all(data).unwrap();
+    file.finish().unwrap();
+}
+
+#[test]
+fn hashed_names_with_identical_content() {
+    let mut writer = writer();
+    add(&mut writer, "voice/a.ogg", Some("0001"), b"same");
+    add(&mut writer, "plain.txt", None, b"other");
+    add(&mut writer, "voice/b.ogg", Some("0002"), b"same");
+    let data = writer.finish(Some(6)).unwrap().into_inner();
+
+    let mut archive = XP3Archive::open(Cursor::new(data)).unwrap();
+    let names = archive
+        .entries()
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.hashed_name.as_deref()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            ("voice/a.ogg", Some("0001")),
+            ("plain.txt", None),
+            ("voice/b.ogg", Some("0002")),
+        ]
+    );
+
+    let mut buf = vec![];
+    archive
+        .by_name("voice/b.ogg")
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"same");
+}
+
+#[test]
+fn ambiguous_hashed_name_is_rejected() {
+    let mut writer = writer();
+    add(&mut writer, "voice/a.ogg", Some("0001"), b"same");
+    add(&mut writer, "copy.ogg", None, b"same");
+
+    let err = writer.finish(None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn protected_flag_round_trip() {
+    let mut writer = writer();
+    for (name, protected) in [("protected.bin", true), ("plain.bin", false)] {
+        let mut file = writer.file(name.into(), protected, None).unwrap();
+        file.write_all(name.as_bytes()).unwrap();
+        file.finish().unwrap();
+    }
+    let data = writer.finish(None).unwrap().into_inner();
+
+    let archive = XP3Archive::open(Cursor::new(data)).unwrap();
+    let protected = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.protected)
+        .collect::<Vec<_>>();
+    assert_eq!(protected, [true, false]);
+}
+
+/// Archive of a hashed file with unknown chunks between known chunks and `eliF` name chunk
+fn reordered_archive() -> Vec<u8> {
+    let mut writer = writer();
+    add(&mut writer, "a.txt", Some("0001"), b"a");
+    let data = writer.finish(None).unwrap().into_inner();
+    let (start, index) = index_data(&data);
+
+    let chunks = split_chunks(index);
+    let (_, name) = chunks.iter().find(|(tag, _)| tag == b"hnfn").unwrap();
+    let (_, file) = chunks.iter().find(|(tag, _)| tag == b"File").unwrap();
+    let file = split_chunks(file);
+    let known = |name: &[u8; 4]| {
+        let (_, data) = file.iter().find(|(tag, _)| tag == name).unwrap();
+        chunk(name, data)
+    };
+    let file = [
+        known(b"segm"),
+        chunk(b"extr", &[9]),
+        known(b"info"),
+        known(b"adlr"),
+    ]
+    .concat();
+
+    let index = [
+        chunk(b"zzzz", &[1, 2]),
+        chunk(b"File", &file),
+        chunk(b"eliF", name),
+        chunk(b"tail", &[3]),
+    ]
+    .concat();
+
+    with_index(&data[..start], &index)
+}
+
+/// Archive with header and file data of `data` followed by uncompressed `index`
+fn with_index(data: &[u8], index: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    data.push(0);
+    data.extend_from_slice(&(index.len() as u64).to_le_bytes());
+    data.extend_from_slice(index);
+    data
+}
+
+#[test]
+fn unknown_chunks_keep_order() {
+    let data = reordered_archive();
+
+    let mut archive = XP3Archive::open(Cursor::new(data.clone())).unwrap();
+    assert_eq!(archive.entries()[0].name, "a.txt");
+    assert_eq!(archive.unknown_chunks().len(), 2);
+    let mut buf = vec![];
+    archive
+        .by_index(0)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"a");
+
+    let appended = XP3Writer::append(Cursor::new(data.clone()))
+        .unwrap()
+        .finish(None)
+        .unwrap()
+        .into_inner();
+    assert_eq!(appended, data);
+
+    let mut writer = writer();
+    writer.copy_layout(&mut archive);
+    writer.copy_raw(&mut archive, 0).unwrap().unwrap();
+    assert_eq!(writer.finish(None).unwrap().into_inner(), data);
+
+    let mut editor = XP3Editor::open(Cursor::new(data.clone())).unwrap();
+    let written = editor
+        .write_to(Cursor::new(vec![]), None)
+        .unwrap()
+        .into_inner();
+    assert_eq!(written, data);
+
+    let mut editor = XP3Editor::open(Cursor::new(data.clone())).unwrap();
+    editor.replace(0, b"b".to_vec(), None);
+    let written = editor
+        .write_to(Cursor::new(vec![]), None)
+        .unwrap()
+        .into_inner();
+    let (_, index) = index_data(&written);
+    let tags = split_chunks(index)
+        .into_iter()
+        .map(|(tag, data)| match &tag {
+            b"File" => split_chunks(data).into_iter().map(|(tag, _)| tag).collect(),
+            _ => vec![tag],
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tags,
+        [
+            vec![*b"zzzz"],
+            vec![*b"segm", *b"extr", *b"info", *b"adlr"],
+            vec![*b"eliF"],
+            vec![*b"tail"],
+        ]
+    );
+}
+
+#[test]
+fn validate_file_source() {
+    let mut writer = writer();
+    add(&mut writer, "a.txt", None, b"a");
+    let mut data = writer.finish(None).unwrap().into_inner();
+
+    let path = env::temp_dir().join(format!("xp3-index-{}.xp3", process::id()));
+    fs::write(&path, &data).unwrap();
+    let archive = XP3SharedArchive::open(File::open(&path).unwrap()).unwrap();
+    assert!(archive.validate().unwrap().is_empty());
+
+    // segm: tag, length, flags, start
+    let segm = data.windows(4).rposition(|tag| tag == b"segm").unwrap() + 16;
+    let end = data.len() as u64;
+    data[segm..segm + 8].copy_from_slice(&end.to_le_bytes());
+    fs::write(&path, &data).unwrap();
+
+    let archive = XP3SharedArchive::open(File::open(&path).unwrap()).unwrap();
+    let problems = archive.validate().unwrap();
+    assert!(!problems.is_empty());
+    assert_eq!(
+        problems.len(),
+        XP3SharedArchive::open(&data[..])
+            .unwrap()
+            .validate()
+            .unwrap()
+            .len()
+    );
+    assert!(matches!(
+        XP3ArchiveOptions::new()
+            .strict(true)
+            .open_shared(File::open(&path).unwrap()),
+        Err(XP3OpenError::Inconsistent(_))
+    ));
+
+    fs::remove_file(&path).unwrap();
+}
+
+/// `info` chunk of file with `name`
+fn info(name: &str, size: u64, archive_size: u64) -> Vec<u8> {
+    let name = name.encode_utf16().collect::<Vec<_>>();
+    let mut data = 0_u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&size.to_le_bytes());
+    data.extend_from_slice(&archive_size.to_le_bytes());
+    data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    data.extend(name.iter().flat_map(|ch| ch.to_le_bytes()));
+    chunk(b"info", &data)
+}
+
+/// `segm` chunk of a stored segment followed by `extra` bytes
+fn segm(start: u64, size: u64, extra: &[u8]) -> Vec<u8> {
+    let mut data = 0_u32.to_le_bytes().to_vec();
+    for value in [start, size, size] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(extra);
+    chunk(b"segm", &data)
+}
+
+#[test]
+fn validate_reports_corrupted_index() {
+    let mut writer = writer();
+    add(&mut writer, "a", None, b"aaaa");
+    add(&mut writer, "b", None, b"bbbbbbbb");
+    let data = writer.finish(None).unwrap().into_inner();
+    let (start, _) = index_data(&data);
+    let a = (start - 12) as u64;
+    let adlr = chunk(b"adlr", &[0; 4]);
+
+    let index = [
+        // Size in info differs from segments
+        chunk(
+            b"File",
+            &[info("a", 5, 4), segm(a, 4, &[]), adlr.clone()].concat(),
+        ),
+        // Archive size differs, segment overlaps `a` and segm chunk has 2 extra bytes
+        chunk(
+            b"File",
+            &[info("b", 8, 9), segm(a + 2, 8, &[0, 0]), adlr].concat(),
+        ),
+        // No info, no adlr and segment past the end
+        chunk(b"File", &segm(1 << 20, 0, &[])),
+    ]
+    .concat();
+    let data = with_index(&data[..start], &index);
+
+    let expected = vec![
+        XP3Problem::SegmentChunkLength {
+            entry: 1,
+            name: "b".into(),
+            length: 30,
+        },
+        XP3Problem::MissingInfo { entry: 2 },
+        XP3Problem::MissingChecksum {
+            entry: 2,
+            name: "".into(),
+        },
+        XP3Problem::SizeMismatch {
+            entry: 0,
+            name: "a".into(),
+            info: 5,
+            segments: 4,
+        },
+        XP3Problem::ArchiveSizeMismatch {
+            entry: 1,
+            name: "b".into(),
+            info: 9,
+            segments: 8,
+        },
+        XP3Problem::SegmentPastEnd {
+            entry: 2,
+            name: "".into(),
+            segment: 0,
+            end: 1 << 20,
+            len: data.len() as u64,
+        },
+        XP3Problem::OverlappingSegments {
+            entry: 1,
+            name: "b".into(),
+            segment: 0,
+            other_entry: 0,
+            other_name: "a".into(),
+            other_segment: 0,
+        },
+    ];
+
+    let mut archive = XP3Archive::open(Cursor::new(data.clone())).unwrap();
+    assert_eq!(archive.validate().unwrap(), expected);
+    let shared = XP3SharedArchive::open(&data[..]).unwrap();
+    assert_eq!(shared.validate().unwrap(), expected);
+    assert_eq!(
+        expected[3].to_string(),
+        "Entry #0 \"a\" size mismatch, info: 5 bytes segments: 4 bytes"
+    );
+
+    match XP3ArchiveOptions::new()
+        .strict(true)
+        .open(Cursor::new(data))
+    {
+        Err(XP3OpenError::Inconsistent(problems)) => assert_eq!(problems, expected),
+        res => panic!("expected inconsistent archive, got {:?}", res.map(|_| ())),
+    }
+}
+
+/// Tags and data of chunks inside of a `File` chunk
+type FileChunks = Vec<([u8; 4], Vec<u8>)>;
+
+/// Header and data of archive with files `a.txt` and `b.txt`, and chunks inside of their `File` chunks
+fn file_chunks() -> (Vec<u8>, Vec<FileChunks>) {
+    let mut writer = writer();
+    add(&mut writer, "a.txt", None, b"a");
+    add(&mut writer, "b.txt", None, b"b");
+    let data = writer.finish(None).unwrap().into_inner();
+    let (start, index) = index_data(&data);
+    let files = split_chunks(index)
+        .into_iter()
+        .map(|(_, file)| {
+            split_chunks(file)
+                .into_iter()
+                .map(|(tag, data)| (tag, data.to_vec()))
+                .collect()
+        })
+        .collect();
+    (data[..start].to_vec(), files)
+}
+
+/// `File` chunk of `chunks`
+fn file_chunk(chunks: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let data = chunks
+        .iter()
+        .flat_map(|(tag, data)| chunk(tag, data))
+        .collect::<Vec<_>>();
+    chunk(b"File", &data)
+}
+
+/// Assert opening `data` fails with `check`ed error, and recovery stops at the same error keeping only `a.txt`
+fn check_damage(data: Vec<u8>, check: impl Fn(XP3OpenError)) {
+    check(XP3Archive::open(Cursor::new(data.clone())).unwrap_err());
+
+    let (archive, recovery) = XP3Archive::open_recover(Cursor::new(data)).unwrap();
+    assert_eq!(recovery.salvaged, 1);
+    assert_eq!(archive.entries()[0].name, "a.txt");
+    check(recovery.error.unwrap());
+}
+
+#[test]
+fn missing_segm_location() {
+    let (data, files) = file_chunks();
+    let a = file_chunk(&files[0]);
+    let b = files[1]
+        .iter()
+        .filter(|(tag, _)| tag != b"segm")
+        .cloned()
+        .collect::<Vec<_>>();
+    let data = with_index(&data, &[a.clone(), file_chunk(&b)].concat());
+
+    check_damage(data, |err| {
+        let XP3OpenError::MissingChunk { location } = err else {
+            panic!("{err:?} is not missing chunk");
+        };
+        assert_eq!(
+            location,
+            IndexLocation {
+                offset: a.len() as u64,
+                path: "File/segm".into(),
+                entry: Some(1),
+                name: Some("b.txt".into()),
+            }
+        );
+        assert_eq!(
+            location.to_string(),
+            format!(
+                "File/segm at index offset {:#X} (entry #1 \"b.txt\")",
+                a.len()
+            )
+        );
+    });
+}
+
+#[test]
+fn truncated_info_location() {
+    let (data, files) = file_chunks();
+    let a = file_chunk(&files[0]);
+    let mut b = files[1].clone();
+    assert_eq!(&b[0].0, b"info");
+    b[0].1.truncate(10);
+    let data = with_index(&data, &[a.clone(), file_chunk(&b)].concat());
+
+    check_damage(data, |err| {
+        let XP3OpenError::TruncatedChunk {
+            location,
+            expected,
+            actual,
+        } = err
+        else {
+            panic!("{err:?} is not truncated chunk");
+        };
+        // Name is not known before info chunk is read
+        assert_eq!(
+            location,
+            IndexLocation {
+                offset: a.len() as u64 + 12,
+                path: "File/info".into(),
+                entry: Some(1),
+                name: None,
+            }
+        );
+        assert_eq!((expected, actual), (22, 10));
+    });
+}
+
+#[test]
+fn truncated_file_chunk_location() {
+    let (data, files) = file_chunks();
+    let a = file_chunk(&files[0]);
+    let mut b = file_chunk(&files[1]);
+    let size = b.len() as u64 - 12;
+    b[4..12].copy_from_slice(&(size + 100).to_le_bytes());
+    let data = with_index(&data, &[a.clone(), b].concat());
+
+    check_damage(data, |err| {
+        let XP3OpenError::TruncatedChunk {
+            location,
+            expected,
+            actual,
+        } = err
+        else {
+            panic!("{err:?} is not truncated chunk");
+        };
+        assert_eq!(
+            location,
+            IndexLocation {
+                offset: a.len() as u64,
+                path: "File".into(),
+                entry: None,
+                name: None,
+            }
+        );
+        assert_eq!((expected, actual), (size + 100, size));
+    });
+}
+
+#[test]
+fn truncated_index() {
+    let (data, files) = file_chunks();
+    let index = [file_chunk(&files[0]), file_chunk(&files[1])].concat();
+    let mut data = with_index(&data, &index);
+    data.truncate(data.len() - 5);
+
+    check_damage(data, |err| {
+        let XP3OpenError::TruncatedIndex { expected, actual } = err else {
+            panic!("{err:?} is not truncated index");
+        };
+        assert_eq!(
+            (expected, actual),
+            (index.len() as u64, index.len() as u64 - 5)
+        );
+    });
+}
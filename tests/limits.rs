@@ -0,0 +1,141 @@
+use std::io::{self, Cursor, Write};
+
+use xp3::{
+    error::{XP3OpenError, XP3ReadError, XP3ReadErrorKind},
+    header::XP3Version,
+    limits::{XP3LimitKind, XP3Limits},
+    options::XP3ArchiveOptions,
+    sync::{XP3Archive, XP3SharedArchive, XP3Writer},
+};
+
+/// Archive of a compressed file with `size` zero bytes, stated as `stated` bytes in index
+fn bomb(size: usize, stated: u64) -> Vec<u8> {
+    misstated(size, stated, Some(9))
+}
+
+/// Archive of a file with `size` zero bytes, stated as `stated` bytes in index
+fn misstated(size: usize, stated: u64, compression: Option<u8>) -> Vec<u8> {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    let mut file = writer.file("bomb.bin".into(), false, compression).unwrap();
+    file.write_all(&vec![0; size]).unwrap();
+    file.finish().unwrap();
+    let mut data = writer.finish(None).unwrap().into_inner();
+
+    // info: tag, length, flags, size
+    let info = find(&data, b"info") + 16;
+    data[info..info + 8].copy_from_slice(&stated.to_le_bytes());
+    // segm: tag, length, flags, start, size
+    let segm = find(&data, b"segm") + 24;
+    data[segm..segm + 8].copy_from_slice(&stated.to_le_bytes());
+    data
+}
+
+fn find(data: &[u8], tag: &[u8]) -> usize {
+    data.windows(tag.len())
+        .rposition(|chunk| chunk == tag)
+        .unwrap()
+}
+
+/// Writer counting bytes written
+#[derive(Default)]
+struct Counter(u64);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn bomb_stops_at_stated_size() {
+    let data = bomb(4 * 1024 * 1024, 16);
+    let mut archive = XP3Archive::open(Cursor::new(data)).unwrap();
+
+    let mut file = archive.by_index(0).unwrap().unwrap();
+    let mut counter = Counter::default();
+    let err = io::copy(&mut file, &mut counter).unwrap_err();
+    let err = XP3ReadError::try_from(err).unwrap();
+
+    assert!(counter.0 <= 16, "{} bytes read", counter.0);
+    assert!(matches!(
+        err.kind,
+        XP3ReadErrorKind::SegmentSize {
+            segment: 0,
+            expected: 16,
+            actual: 17,
+        }
+    ));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn bomb_stops_at_stated_size_async() {
+    let data = bomb(4 * 1024 * 1024, 16);
+    let mut archive = xp3::read::XP3Archive::open(Cursor::new(data))
+        .await
+        .unwrap();
+
+    let mut file = archive.by_index(0).await.unwrap().unwrap();
+    let mut sink = tokio::io::sink();
+    let err = tokio::io::copy(&mut file, &mut sink).await.unwrap_err();
+    let err = XP3ReadError::try_from(err).unwrap();
+
+    assert!(matches!(
+        err.kind,
+        XP3ReadErrorKind::SegmentSize {
+            expected: 16,
+            actual: 17,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn bytes_checks_stated_size() {
+    let data = bomb(4 * 1024 * 1024, 16);
+    let archive = XP3SharedArchive::open(&data[..]).unwrap();
+    let err = archive.bytes(0).unwrap().unwrap_err();
+    assert!(matches!(
+        err.kind,
+        XP3ReadErrorKind::SegmentSize {
+            expected: 16,
+            actual: 17,
+            ..
+        }
+    ));
+
+    let data = misstated(1000, 16, None);
+    let archive = XP3SharedArchive::open(&data[..]).unwrap();
+    let err = archive.bytes(0).unwrap().unwrap_err();
+    assert!(matches!(
+        err.kind,
+        XP3ReadErrorKind::SegmentSize {
+            expected: 16,
+            actual: 1000,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn segment_compression_ratio() {
+    let data = bomb(1024, 1 << 40);
+    let err = XP3Archive::open(Cursor::new(data.clone())).unwrap_err();
+    assert!(matches!(
+        err,
+        XP3OpenError::LimitExceeded {
+            kind: XP3LimitKind::SegmentCompressionRatio,
+            ..
+        }
+    ));
+
+    XP3ArchiveOptions::new()
+        .limits(XP3Limits::UNLIMITED)
+        .open(Cursor::new(data))
+        .unwrap();
+}
@@ -0,0 +1,213 @@
+use std::{
+    io::{Cursor, Read},
+    path::PathBuf,
+};
+
+use xp3::{
+    name::{NameFix, NameMatching, sanitize_path},
+    sync::{XP3Archive, XP3SharedArchive},
+};
+
+mod common;
+
+use common::{archive, content};
+
+/// Names of entries in [`lookup_archive`]
+const NAMES: [&str; 4] = ["Dir/Ä.txt", "data\\Image.PNG", "Same", "same"];
+
+/// Lookups with normalized matching and expected entry index, none if only exact names match
+const LOOKUPS: [(&str, Option<usize>); 6] = [
+    ("DIR\\Ä.TXT", Some(0)),
+    // Only ASCII letters are folded like Kirikiri
+    ("dir/ä.txt", None),
+    ("data/image.png", Some(1)),
+    ("DATA\\IMAGE.png", Some(1)),
+    // Duplicated names resolve to the first entry
+    ("SAME", Some(2)),
+    ("Dir/A.txt", None),
+];
+
+fn lookup_archive() -> Vec<u8> {
+    archive(&NAMES)
+}
+
+/// Assert `index_of` finds only exact names
+fn check_exact(index_of: impl Fn(&str) -> Option<usize>) {
+    for (index, name) in NAMES.iter().enumerate() {
+        assert_eq!(index_of(name), Some(index), "{name}");
+    }
+    for (name, _) in LOOKUPS {
+        assert_eq!(index_of(name), None, "{name}");
+    }
+}
+
+/// Assert `index_of` finds normalized names
+fn check_normalized(index_of: impl Fn(&str) -> Option<usize>) {
+    for (name, expected) in LOOKUPS {
+        assert_eq!(index_of(name), expected, "{name}");
+    }
+}
+
+/// Assert `name` is sanitized into path of `parts` with `fixes`
+fn check(name: &str, parts: &[&str], fixes: &[NameFix]) {
+    let sanitized = sanitize_path(name);
+    assert_eq!(
+        sanitized.path,
+        parts.iter().collect::<PathBuf>(),
+        "path of {name:?}"
+    );
+    assert_eq!(sanitized.fixes, fixes, "fixes of {name:?}");
+}
+
+#[test]
+fn safe_names() {
+    check("data/image.png", &["data", "image.png"], &[]);
+    check("./a/./b", &["a", "b"], &[]);
+    check("a//b", &["a", "b"], &[]);
+    check("COM10.txt", &["COM10.txt"], &[]);
+    check("console.log", &["console.log"], &[]);
+}
+
+#[test]
+fn traversal() {
+    check(
+        "../../etc/passwd",
+        &["etc", "passwd"],
+        &[NameFix::ParentDir],
+    );
+    check("a/../../b", &["a", "b"], &[NameFix::ParentDir]);
+    check("a\\..\\..\\b", &["a", "b"], &[NameFix::ParentDir]);
+    check("..", &[], &[NameFix::ParentDir]);
+}
+
+#[test]
+fn absolute_and_drive() {
+    check("/etc/passwd", &["etc", "passwd"], &[NameFix::Root]);
+    check(
+        "\\Windows\\win.ini",
+        &["Windows", "win.ini"],
+        &[NameFix::Root],
+    );
+    check(
+        "C:\\Windows\\win.ini",
+        &["Windows", "win.ini"],
+        &[NameFix::Drive],
+    );
+    check("c:relative.txt", &["relative.txt"], &[NameFix::Drive]);
+    check(
+        "/D:/../x",
+        &["x"],
+        &[NameFix::Root, NameFix::Drive, NameFix::ParentDir],
+    );
+    check("/", &[], &[NameFix::Root]);
+}
+
+#[test]
+fn unc() {
+    check(
+        "\\\\server\\share\\file.txt",
+        &["server", "share", "file.txt"],
+        &[NameFix::Root],
+    );
+    check(
+        "\\\\?\\C:\\x",
+        &["_", "C_", "x"],
+        &[
+            NameFix::Root,
+            NameFix::InvalidChar('?'),
+            NameFix::InvalidChar(':'),
+        ],
+    );
+}
+
+#[test]
+fn reserved_names() {
+    check(
+        "con.txt",
+        &["_con.txt"],
+        &[NameFix::Reserved("con.txt".into())],
+    );
+    check(
+        "dir/LPT1",
+        &["dir", "_LPT1"],
+        &[NameFix::Reserved("LPT1".into())],
+    );
+    check(
+        "Aux .tar.gz",
+        &["_Aux .tar.gz"],
+        &[NameFix::Reserved("Aux .tar.gz".into())],
+    );
+    // Replaced trailing dot leaves no reserved name
+    check("NUL.", &["NUL_"], &[NameFix::TrailingDot]);
+}
+
+#[test]
+fn invalid_chars() {
+    check(
+        "a<b>.txt",
+        &["a_b_.txt"],
+        &[NameFix::InvalidChar('<'), NameFix::InvalidChar('>')],
+    );
+    check("nul\0.txt", &["nul_.txt"], &[NameFix::Nul]);
+    check("tab\there", &["tab_here"], &[NameFix::InvalidChar('\t')]);
+    check("name. .", &["name___"], &[NameFix::TrailingDot]);
+}
+
+#[test]
+fn normalized_lookup() {
+    let mut archive = XP3Archive::open(Cursor::new(lookup_archive())).unwrap();
+    check_exact(|name| archive.index_of(name));
+    archive.set_name_matching(NameMatching::Normalized);
+    check_normalized(|name| archive.index_of(name));
+
+    let mut buf = vec![];
+    archive
+        .by_name("data/image.png")
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, content(NAMES[1]));
+}
+
+#[test]
+fn normalized_lookup_shared() {
+    let data = lookup_archive();
+    let mut archive = XP3SharedArchive::open(&data[..]).unwrap();
+    check_exact(|name| archive.index_of(name));
+    archive.set_name_matching(NameMatching::Normalized);
+    check_normalized(|name| archive.index_of(name));
+
+    let mut buf = vec![];
+    archive
+        .by_name("DIR\\Ä.txt")
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, content(NAMES[0]));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn normalized_lookup_async() {
+    use tokio::io::AsyncReadExt;
+
+    let mut archive = xp3::read::XP3Archive::open(Cursor::new(lookup_archive()))
+        .await
+        .unwrap();
+    check_exact(|name| archive.index_of(name));
+    archive.set_name_matching(NameMatching::Normalized);
+    check_normalized(|name| archive.index_of(name));
+
+    let mut buf = vec![];
+    archive
+        .by_name("same")
+        .await
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buf)
+        .await
+        .unwrap();
+    assert_eq!(buf, content(NAMES[2]));
+}
@@ -0,0 +1,142 @@
+use std::io::{Cursor, Write};
+
+use xp3::{
+    header::XP3Version,
+    limits::{XP3LimitKind, XP3Limits},
+    name::NameMatching,
+    options::XP3ArchiveOptions,
+    sync::{XP3Archive, XP3Recovery, XP3Writer},
+};
+
+mod common;
+
+use common::{archive, assert_limit_exceeded, content, read_all};
+
+#[test]
+fn recover_with_options() {
+    let mut options = XP3ArchiveOptions::new();
+    options
+        .name_matching(NameMatching::Normalized)
+        .verify_checksum(true)
+        .limits(XP3Limits {
+            max_entries: 1,
+            ..XP3Limits::DEFAULT
+        });
+    let (mut archive, recovery) = options
+        .open_recover(Cursor::new(archive(&["Dir/A.txt", "b.txt", "c.txt"])))
+        .unwrap();
+
+    assert_limit_exceeded(recovery.error.as_ref().unwrap(), XP3LimitKind::Entries);
+    assert_eq!(recovery.salvaged, 1);
+    assert_eq!(recovery.carved, 2);
+    assert!(archive.verify_checksum());
+    assert_eq!(archive.index_of("dir\\a.txt"), Some(0));
+    assert_eq!(read_all(&mut archive, 2), content("c.txt"));
+}
+
+/// Index offset of archive and position where it is stored
+fn index_offset(data: &[u8]) -> (usize, usize) {
+    // Index offset is at the end of header, followed by an empty index in an empty archive
+    let header_len = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![]))
+        .unwrap()
+        .finish(None)
+        .unwrap()
+        .into_inner()
+        .len()
+        - 9;
+    let offset = &data[header_len - 8..header_len];
+    (
+        u64::from_le_bytes(offset.try_into().unwrap()) as usize,
+        header_len - 8,
+    )
+}
+
+/// Recover archive and read its entries
+fn recover(data: Vec<u8>) -> (XP3Recovery, Vec<(String, Vec<u8>)>) {
+    let (mut archive, recovery) = XP3Archive::open_recover(Cursor::new(data)).unwrap();
+    assert_eq!(recovery.salvaged + recovery.carved, archive.entries().len());
+    let files = (0..archive.entries().len())
+        .map(|index| {
+            (
+                archive.entries()[index].name.clone(),
+                read_all(&mut archive, index),
+            )
+        })
+        .collect();
+    (recovery, files)
+}
+
+#[test]
+fn recover_damaged_index_header() {
+    let names = ["a.txt", "b.txt", "c.txt"];
+    let data = archive(&names);
+    let (index, offset_pos) = index_offset(&data);
+
+    let (recovery, files) = recover(data.clone());
+    assert!(recovery.error.is_none());
+    assert_eq!(recovery.salvaged, 3);
+    assert_eq!(files.len(), 3);
+
+    // Damaged data, its description and number of entries salvaged from index
+    let mut damaged = vec![];
+    let mut past_end = data.clone();
+    past_end[offset_pos..offset_pos + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    damaged.push((past_end, "index offset past the end", 0));
+
+    let mut flag = data.clone();
+    flag[index] = 0;
+    damaged.push((flag, "compressed index flagged as stored", 0));
+
+    let mut size = data.clone();
+    size[index + 1..index + 9].copy_from_slice(&(1_u64 << 20).to_le_bytes());
+    damaged.push((size, "index size past the end", 3));
+
+    let mut garbled = data.clone();
+    for byte in &mut garbled[index + 17..] {
+        *byte = !*byte;
+    }
+    damaged.push((garbled, "garbled index data", 0));
+
+    for (data, damage, expected_salvaged) in damaged {
+        let (recovery, files) = recover(data);
+        assert!(recovery.error.is_some(), "{damage}");
+        assert_eq!(recovery.salvaged, expected_salvaged, "{damage}");
+        // Compressed index is carved too if it lies in the scanned region
+        assert!(files.len() >= 3, "{damage}");
+        for ((name, data), expected) in files.iter().zip(names) {
+            if recovery.salvaged == 0 {
+                assert!(name.starts_with("recovered/"), "{damage}: {name}");
+            } else {
+                assert_eq!(name, expected, "{damage}");
+            }
+            assert_eq!(*data, content(expected), "{damage}: {name}");
+        }
+    }
+}
+
+#[test]
+fn recover_past_false_headers() {
+    // Stored decoy full of zlib header lookalikes, followed by compressed file whose header
+    // crosses the end of the first scanned block
+    let decoy = [0x78, 0x01].repeat(32 * 1024)[..64 * 1024 - 1].to_vec();
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    let mut file = writer.file("decoy".into(), false, None).unwrap();
+    file.write_all(&decoy).unwrap();
+    file.finish().unwrap();
+    let mut file = writer.file("a.txt".into(), false, Some(6)).unwrap();
+    file.write_all(&content("a.txt")).unwrap();
+    file.finish().unwrap();
+    let mut data = writer.finish(None).unwrap().into_inner();
+
+    let (_, offset_pos) = index_offset(&data);
+    data[offset_pos..offset_pos + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+
+    let (recovery, files) = recover(data);
+    assert_eq!(recovery.salvaged, 0);
+    assert_eq!(recovery.carved, 1);
+    assert_eq!(
+        files[0].0,
+        format!("recovered/{:08X}", offset_pos + 8 + decoy.len())
+    );
+    assert_eq!(files[0].1, content("a.txt"));
+}
@@ -0,0 +1,154 @@
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use xp3::{
+    header::XP3Version,
+    sync::{XP3Archive, XP3SharedArchive, XP3Writer},
+};
+
+mod common;
+
+use common::finished;
+
+/// Seeks crossing segment boundaries backward and forward, and past the end
+const SEEKS: [SeekFrom; 12] = [
+    SeekFrom::Start(0),
+    SeekFrom::Start(990),
+    SeekFrom::Start(1000),
+    SeekFrom::Current(1500),
+    SeekFrom::Current(-2000),
+    SeekFrom::End(-1),
+    SeekFrom::End(-1505),
+    SeekFrom::Current(-1),
+    SeekFrom::Start(3999),
+    SeekFrom::Current(0),
+    SeekFrom::Start(10),
+    SeekFrom::End(100),
+];
+
+/// Bytes read after each seek
+const READ: usize = 20;
+
+fn sample() -> Vec<u8> {
+    let mut state = 7_u32;
+    (0..4500)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 20) as u8 % 16
+        })
+        .collect()
+}
+
+/// Archive of compressed `a`, its duplicate `b` and stored `c`, in segments of 1000 bytes
+fn archive() -> Vec<u8> {
+    let data = sample();
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    writer.set_segment_size(Some(1000));
+    writer.set_deduplicate(true);
+    for (name, compression) in [("a", Some(9)), ("b", Some(9)), ("c", None)] {
+        let mut file = writer.file(name.into(), false, compression).unwrap();
+        file.write_all(&data).unwrap();
+        file.finish().unwrap();
+    }
+    assert!(writer.deduplicated_size() > 0);
+    finished(writer.finish(None).unwrap())
+}
+
+/// Expected position after `seek` from `pos`
+fn seek_position(pos: u64, size: u64, seek: SeekFrom) -> u64 {
+    match seek {
+        SeekFrom::Start(offset) => offset,
+        SeekFrom::End(offset) => size.checked_add_signed(offset).unwrap(),
+        SeekFrom::Current(offset) => pos.checked_add_signed(offset).unwrap(),
+    }
+}
+
+/// Expected bytes read at `pos`
+fn expected(data: &[u8], pos: u64) -> &[u8] {
+    let start = (pos as usize).min(data.len());
+    &data[start..(start + READ).min(data.len())]
+}
+
+fn check_seeks(file: &mut (impl Read + Seek), data: &[u8]) {
+    let mut pos = 0;
+    for seek in SEEKS {
+        pos = seek_position(pos, data.len() as u64, seek);
+        assert_eq!(file.seek(seek).unwrap(), pos, "{seek:?}");
+
+        let mut buf = vec![];
+        file.by_ref()
+            .take(READ as u64)
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, expected(data, pos), "{seek:?} to {pos}");
+        pos += buf.len() as u64;
+    }
+
+    let err = file.seek(SeekFrom::Current(-(pos as i64) - 1)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn seek_segments() {
+    let data = sample();
+    let mut archive = XP3Archive::open(Cursor::new(archive())).unwrap();
+    for index in 0..3 {
+        let mut file = archive.by_index(index).unwrap().unwrap();
+        check_seeks(&mut file, &data);
+    }
+}
+
+#[test]
+fn seek_segments_shared() {
+    let data = sample();
+    let archive = archive();
+    let archive = XP3SharedArchive::open(&archive[..]).unwrap();
+    let mut files = (0..3)
+        .map(|index| archive.by_index(index).unwrap().unwrap())
+        .collect::<Vec<_>>();
+    // Deduplicated files read the same data through independent cursors
+    for file in &mut files {
+        check_seeks(file, &data);
+    }
+}
+
+#[test]
+fn seek_then_verify_checksum() {
+    let data = sample();
+    let mut archive = XP3Archive::open(Cursor::new(archive())).unwrap();
+    archive.set_verify_checksum(true);
+
+    let mut file = archive.by_index(1).unwrap().unwrap();
+    file.seek(SeekFrom::Start(2500)).unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut buf = vec![];
+    file.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn seek_segments_async() {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let data = sample();
+    let mut archive = xp3::read::XP3Archive::open(Cursor::new(archive()))
+        .await
+        .unwrap();
+    for index in 0..3 {
+        let mut file = archive.by_index(index).await.unwrap().unwrap();
+        let mut pos = 0;
+        for seek in SEEKS {
+            pos = seek_position(pos, data.len() as u64, seek);
+            assert_eq!(file.seek(seek).await.unwrap(), pos, "{seek:?}");
+
+            let mut buf = vec![];
+            (&mut file)
+                .take(READ as u64)
+                .read_to_end(&mut buf)
+                .await
+                .unwrap();
+            assert_eq!(buf, expected(&data, pos), "{seek:?} to {pos}");
+            pos += buf.len() as u64;
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    borrow::Cow,
+    env, fs,
+    io::{Cursor, Read, Write},
+    path::PathBuf,
+    process,
+    sync::Arc,
+    thread,
+};
+
+use xp3::{
+    crypt::Xor,
+    header::XP3Version,
+    sync::{XP3SharedArchive, XP3Writer, read::ReadAt},
+};
+
+mod common;
+
+use common::{content, noise};
+
+/// Names, compression and data of files: compressed, stored, multi-segment and empty
+fn files() -> Vec<(String, Option<u8>, Vec<u8>)> {
+    vec![
+        ("single.bin".into(), None, noise(3, 500)),
+        ("compressed.txt".into(), Some(6), content("compressed")),
+        ("stored.bin".into(), None, noise(1, 3000)),
+        ("segments.bin".into(), Some(9), noise(2, 5000)),
+        ("empty".into(), None, vec![]),
+    ]
+}
+
+/// Archive of [`files`], split in segments of 1000 bytes
+fn archive() -> Vec<u8> {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    writer.set_segment_size(Some(1000));
+    for (name, compression, data) in files() {
+        let mut file = writer.file(name, false, compression).unwrap();
+        file.write_all(&data).unwrap();
+        file.finish().unwrap();
+    }
+    writer.finish(None).unwrap().into_inner()
+}
+
+/// Archive of [`files`] written to a temporary file for test `name`
+fn archive_file(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("xp3-shared-{name}-{}.xp3", process::id()));
+    fs::write(&path, archive()).unwrap();
+    path
+}
+
+/// Read files of `archive` in an order depending on `seed` in small chunks and compare with [`files`]
+fn read_files<R: ReadAt>(archive: &XP3SharedArchive<R>, seed: usize) {
+    let files = files();
+    for i in 0..files.len() {
+        let index = (i + seed) % files.len();
+        let mut file = archive.by_index(index).unwrap().unwrap();
+        let mut buf = vec![];
+        let mut chunk = [0; 97];
+        loop {
+            let read = file.read(&mut chunk).unwrap();
+            if read == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..read]);
+            thread::yield_now();
+        }
+        assert_eq!(buf, files[index].2, "{}", files[index].0);
+    }
+}
+
+#[test]
+fn by_index_concurrent_scoped() {
+    let path = archive_file("scoped");
+    let mut archive = XP3SharedArchive::open_path(&path).unwrap();
+    archive.set_verify_checksum(true);
+
+    thread::scope(|scope| {
+        for seed in 0..8 {
+            let archive = &archive;
+            scope.spawn(move || read_files(archive, seed));
+        }
+    });
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn by_index_concurrent_arc() {
+    let path = archive_file("arc");
+    let archive = Arc::new(XP3SharedArchive::open_path(&path).unwrap());
+
+    let threads = (0..8)
+        .map(|seed| {
+            let archive = Arc::clone(&archive);
+            thread::spawn(move || read_files(&archive, seed))
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+/// Check content of every file read by [`XP3SharedArchive::bytes`].
+/// Stored single segment files must be borrowed, others owned
+fn check_bytes<R: ReadAt + AsRef<[u8]>>(archive: &XP3SharedArchive<R>) {
+    for (index, (name, _, data)) in files().into_iter().enumerate() {
+        let bytes = archive.bytes(index).unwrap().unwrap();
+        assert_eq!(*bytes, data, "{name}");
+        let borrowed = matches!(bytes, Cow::Borrowed(_));
+        assert_eq!(borrowed, matches!(&*name, "single.bin" | "empty"), "{name}");
+    }
+}
+
+#[test]
+fn bytes_borrow_stored_single_segment() {
+    let data = archive();
+    let mut archive = XP3SharedArchive::open(&data[..]).unwrap();
+    archive.set_verify_checksum(true);
+    check_bytes(&archive);
+
+    // Borrowed data is sliced from the archive without copying
+    let Cow::Borrowed(bytes) = archive.bytes(0).unwrap().unwrap() else {
+        unreachable!();
+    };
+    assert!(data.as_ptr_range().contains(&bytes.as_ptr()));
+}
+
+#[test]
+fn bytes_with_filter_are_owned() {
+    let data = archive();
+    let mut archive = XP3SharedArchive::open(&data[..]).unwrap();
+    archive.set_filter(Some(Arc::new(Xor(0))));
+    let bytes = archive.bytes(0).unwrap().unwrap();
+    assert!(matches!(bytes, Cow::Owned(_)));
+    assert_eq!(*bytes, files()[0].2);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn bytes_mmap() {
+    let path = archive_file("mmap");
+    let archive = unsafe { XP3SharedArchive::open_mmap(&path) }.unwrap();
+    check_bytes(&archive);
+    drop(archive);
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn bytes_bytes() {
+    let archive = XP3SharedArchive::open(bytes::Bytes::from(archive())).unwrap();
+    check_bytes(&archive);
+}
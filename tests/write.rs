@@ -0,0 +1,382 @@
+use std::{
+    cell::Cell,
+    io::{self, Cursor, Seek, SeekFrom, Write},
+    rc::Rc,
+};
+
+use xp3::{
+    header::XP3Version,
+    limits::{XP3LimitKind, XP3Limits},
+    sync::{XP3Archive, XP3PackFile, XP3Writer},
+};
+
+mod common;
+
+use common::{
+    archive, assert_limit_exceeded, content, file_segments, finished, noise, read_all, sample,
+    with_limits,
+};
+
+#[test]
+fn append_with_options() {
+    let options = with_limits(XP3Limits {
+        max_entries: 1,
+        ..XP3Limits::DEFAULT
+    });
+    let err = options
+        .append(Cursor::new(archive(&["a", "b"])))
+        .unwrap_err();
+    assert_limit_exceeded(&err, XP3LimitKind::Entries);
+
+    let mut writer = XP3Writer::append(Cursor::new(archive(&["a", "b"]))).unwrap();
+    let mut file = writer.file("c".into(), false, None).unwrap();
+    file.write_all(b"c").unwrap();
+    file.finish().unwrap();
+    let mut archive =
+        XP3Archive::open(Cursor::new(writer.finish(None).unwrap().into_inner())).unwrap();
+    assert_eq!(read_all(&mut archive, 2), b"c");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn append_async_with_options() {
+    let options = with_limits(XP3Limits {
+        max_entries: 1,
+        ..XP3Limits::DEFAULT
+    });
+    let err = options
+        .append_async(Cursor::new(archive(&["a", "b"])))
+        .await
+        .unwrap_err();
+    assert_limit_exceeded(&err, XP3LimitKind::Entries);
+}
+
+/// Archive of [`sample`] written as three files and bytes saved by deduplication
+fn duplicates(deduplicate: bool) -> (Vec<u8>, u64) {
+    let data = sample();
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    writer.set_deduplicate(deduplicate);
+    for name in ["a.bin", "b.bin", "c.bin"] {
+        let mut file = writer.file(name.into(), false, None).unwrap();
+        file.write_all(&data).unwrap();
+        file.finish().unwrap();
+    }
+
+    let saved = writer.deduplicated_size();
+    (finished(writer.finish(None).unwrap()), saved)
+}
+
+#[test]
+fn deduplicate_shrinks_archive() {
+    let (plain, _) = duplicates(false);
+    let (data, saved) = duplicates(true);
+    assert_eq!(saved, 2 * sample().len() as u64);
+    assert_eq!(data.len() as u64, plain.len() as u64 - saved);
+
+    let mut archive = XP3Archive::open(Cursor::new(data)).unwrap();
+    assert!(archive.validate().unwrap().is_empty());
+    assert_eq!(read_all(&mut archive, 2), sample());
+}
+
+#[test]
+fn deduplicate_keeps_checksum_collisions() {
+    // Both have the same size and adler32 checksum
+    let files: [&[u8]; 2] = [&[1, 2, 1], &[2, 0, 2]];
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    writer.set_deduplicate(true);
+    for (i, data) in files.iter().enumerate() {
+        let mut file = writer.file(i.to_string(), false, None).unwrap();
+        file.write_all(data).unwrap();
+        file.finish().unwrap();
+    }
+    assert_eq!(writer.deduplicated_size(), 0);
+
+    let mut archive =
+        XP3Archive::open(Cursor::new(finished(writer.finish(None).unwrap()))).unwrap();
+    assert_eq!(archive.entries()[0].checksum, archive.entries()[1].checksum);
+    assert_eq!(read_all(&mut archive, 0), files[0]);
+    assert_eq!(read_all(&mut archive, 1), files[1]);
+}
+
+/// Stream failing writes while `fail` is set
+struct Failing {
+    stream: Cursor<Vec<u8>>,
+    fail: Rc<Cell<bool>>,
+}
+
+impl Write for Failing {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.fail.get() {
+            return Err(io::ErrorKind::Other.into());
+        }
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Seek for Failing {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.stream.seek(pos)
+    }
+}
+
+#[test]
+fn deduplicate_ignores_failed_pack() {
+    let fail = Rc::new(Cell::new(false));
+    let stream = Failing {
+        stream: Cursor::new(vec![]),
+        fail: fail.clone(),
+    };
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, stream).unwrap();
+    writer.set_deduplicate(true);
+    let file = |name: &str| XP3PackFile {
+        name: name.into(),
+        data: sample(),
+        ..Default::default()
+    };
+
+    fail.set(true);
+    assert!(writer.pack([file("a.bin")], 1).is_err());
+    fail.set(false);
+    // Content of the file never added is not shared
+    assert_eq!(
+        writer.pack([file("a.bin"), file("b.bin")], 1).unwrap(),
+        [0, 1]
+    );
+    assert_eq!(writer.deduplicated_size() as usize, sample().len());
+
+    let mut archive =
+        XP3Archive::open(Cursor::new(finished(writer.finish(None).unwrap().stream))).unwrap();
+    assert!(archive.validate().unwrap().is_empty());
+    assert_eq!(read_all(&mut archive, 1), sample());
+}
+
+#[test]
+fn append_shorter_index_ends_archive() {
+    let names = (0..100).map(|i| format!("file{i}")).collect::<Vec<_>>();
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    for name in &names {
+        let mut file = writer.file(name.clone(), false, None).unwrap();
+        file.write_all(name.as_bytes()).unwrap();
+        file.finish().unwrap();
+    }
+    let data = writer.finish(None).unwrap().into_inner();
+
+    let writer = XP3Writer::append(Cursor::new(data.clone())).unwrap();
+    let appended = finished(writer.finish(Some(9)).unwrap());
+    assert!(appended.len() < data.len());
+
+    let mut archive = XP3Archive::open(Cursor::new(appended)).unwrap();
+    assert!(archive.validate().unwrap().is_empty());
+    assert_eq!(read_all(&mut archive, 99), b"file99");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn deduplicate_shrinks_archive_async() {
+    use tokio::io::AsyncWriteExt;
+
+    let data = sample();
+    let mut writer =
+        xp3::write::XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![]))
+            .await
+            .unwrap();
+    writer.set_deduplicate(true);
+    for name in ["a.bin", "b.bin", "c.bin"] {
+        let mut file = writer.file(name.into(), false, None).await.unwrap();
+        file.write_all(&data).await.unwrap();
+        file.finish().await.unwrap();
+    }
+
+    let saved = writer.deduplicated_size();
+    let stream = writer.finish(None).await.unwrap();
+    assert_eq!((finished(stream), saved), duplicates(true));
+}
+
+#[test]
+fn lower_segment_size_mid_segment() {
+    let data = sample();
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    let mut file = writer.file("a.bin".into(), false, Some(6)).unwrap();
+    file.segment_size(Some(100));
+    file.write_all(&data[..50]).unwrap();
+    file.segment_size(Some(10));
+    assert_eq!(file.write(&[]).unwrap(), 0);
+    file.write_all(&data[50..]).unwrap();
+    file.finish().unwrap();
+
+    let mut archive =
+        XP3Archive::open(Cursor::new(writer.finish(None).unwrap().into_inner())).unwrap();
+    assert!(archive.validate().unwrap().is_empty());
+    assert_eq!(read_all(&mut archive, 0), data);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn lower_segment_size_mid_segment_async() {
+    use tokio::io::AsyncWriteExt;
+
+    let data = sample();
+    let mut writer =
+        xp3::write::XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![]))
+            .await
+            .unwrap();
+    let mut file = writer.file("a.bin".into(), false, Some(6)).await.unwrap();
+    file.segment_size(Some(100));
+    file.write_all(&data[..50]).await.unwrap();
+    file.segment_size(Some(10));
+    assert_eq!(file.write(&[]).await.unwrap(), 0);
+    file.write_all(&data[50..]).await.unwrap();
+    file.finish().await.unwrap();
+
+    let data_archive = writer.finish(None).await.unwrap().into_inner();
+    let mut archive = XP3Archive::open(Cursor::new(data_archive)).unwrap();
+    assert_eq!(read_all(&mut archive, 0), data);
+}
+
+/// Incompressible data of two segments followed by compressible data of one segment
+fn mixed() -> Vec<u8> {
+    let mut data = noise(1, 2000);
+    data.extend(content("mixed"));
+    data
+}
+
+/// Assert only the compressible segment of [`mixed`] file is compressed if `adaptive`
+fn check_adaptive(data: Vec<u8>, adaptive: bool) {
+    let segments = &file_segments(&data)[0];
+    assert_eq!(segments.len(), 3);
+    for segment in &segments[..2] {
+        assert_eq!(segment.compressed, !adaptive);
+        assert_eq!(segment.archive_size == segment.size, adaptive);
+    }
+    assert!(segments[2].compressed);
+    assert!(segments[2].archive_size < segments[2].size);
+
+    let mut archive = XP3Archive::open(Cursor::new(data)).unwrap();
+    archive.set_verify_checksum(true);
+    assert_eq!(read_all(&mut archive, 0), mixed());
+}
+
+#[test]
+fn adaptive_compression_stores_incompressible_segments() {
+    for adaptive in [true, false] {
+        let mut writer =
+            XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+        writer.set_segment_size(Some(1000));
+        writer.set_adaptive_compression(adaptive);
+        let mut file = writer.file("mixed".into(), false, Some(9)).unwrap();
+        file.write_all(&mixed()).unwrap();
+        file.finish().unwrap();
+        check_adaptive(writer.finish(None).unwrap().into_inner(), adaptive);
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn adaptive_compression_stores_incompressible_segments_async() {
+    use tokio::io::AsyncWriteExt;
+
+    for adaptive in [true, false] {
+        let mut writer =
+            xp3::write::XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![]))
+                .await
+                .unwrap();
+        writer.set_segment_size(Some(1000));
+        writer.set_adaptive_compression(adaptive);
+        let mut file = writer.file("mixed".into(), false, Some(9)).await.unwrap();
+        file.write_all(&mixed()).await.unwrap();
+        file.finish().await.unwrap();
+        check_adaptive(writer.finish(None).await.unwrap().into_inner(), adaptive);
+    }
+}
+
+#[test]
+fn adaptive_compression_bounds_segments() {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    writer.set_adaptive_compression(true);
+    let data = vec![0; (4 << 20) + 100];
+    let mut file = writer.file("zero".into(), false, Some(6)).unwrap();
+    file.write_all(&data).unwrap();
+    file.finish().unwrap();
+    let mut file = writer.file("stored".into(), false, None).unwrap();
+    file.write_all(&data).unwrap();
+    file.finish().unwrap();
+    let archive_data = writer.finish(None).unwrap().into_inner();
+
+    // Segments buffered in memory are split even without segment size, stored files are not
+    let sizes = file_segments(&archive_data)
+        .iter()
+        .map(|segments| segments.iter().map(|segment| segment.size).collect())
+        .collect::<Vec<Vec<_>>>();
+    assert_eq!(sizes, [vec![4 << 20, 100], vec![(4 << 20) + 100]]);
+
+    let mut archive = XP3Archive::open(Cursor::new(archive_data)).unwrap();
+    archive.set_verify_checksum(true);
+    assert_eq!(read_all(&mut archive, 0), data);
+}
+
+/// Files with distinct, repeated and empty data
+fn pack_files() -> Vec<XP3PackFile> {
+    let data = sample();
+    [
+        ("a.bin", data.clone(), Some(9)),
+        ("b.bin", data, Some(9)),
+        ("c.txt", b"hello".to_vec(), None),
+        ("empty", vec![], Some(6)),
+    ]
+    .into_iter()
+    .map(|(name, data, compression)| XP3PackFile {
+        name: name.into(),
+        compression,
+        data,
+        ..Default::default()
+    })
+    .collect()
+}
+
+#[test]
+fn pack_is_deterministic() {
+    let pack = |threads| {
+        let mut writer =
+            XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+        writer.set_segment_size(Some(300));
+        writer.set_deduplicate(true);
+        writer.pack(pack_files(), threads).unwrap();
+        writer.finish(Some(9)).unwrap().into_inner()
+    };
+
+    let data = pack(1);
+    assert_eq!(pack(4), data);
+    let mut archive = XP3Archive::open(Cursor::new(data)).unwrap();
+    assert_eq!(read_all(&mut archive, 1), sample());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn pack_async_matches_sync() {
+    let mut writer = XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![])).unwrap();
+    writer.set_segment_size(Some(300));
+    writer.set_adaptive_compression(true);
+    writer.set_deduplicate(true);
+    let ids = writer.pack(pack_files(), 3).unwrap();
+    let expected = writer.finish(Some(9)).unwrap().into_inner();
+
+    let mut writer =
+        xp3::write::XP3Writer::new(XP3Version::Current { minor: 1 }, Cursor::new(vec![]))
+            .await
+            .unwrap();
+    writer.set_segment_size(Some(300));
+    writer.set_adaptive_compression(true);
+    writer.set_deduplicate(true);
+    assert_eq!(writer.pack(pack_files(), 1).await.unwrap(), ids);
+    let data = writer.finish(Some(9)).await.unwrap().into_inner();
+    assert_eq!(data, expected);
+
+    let mut archive = XP3Archive::open(Cursor::new(data)).unwrap();
+    assert!(archive.validate().unwrap().is_empty());
+    assert_eq!(read_all(&mut archive, 1), sample());
+    assert_eq!(read_all(&mut archive, 3), b"");
+}